@@ -11,6 +11,96 @@ fn sky(rd: vec3<f32>) -> vec3<f32>{
 
 }
 
+fn load_height(box_texture: texture_2d<f32>, c: vec2<i32>) -> f32 {
+    let dims = vec2<i32>(textureDimensions(box_texture));
+    if any(c < vec2(0)) || any(c >= dims) {
+        return 0.0;
+    }
+    return textureLoad(box_texture, c, 0).r;
+}
+
+// The lighting of everything drawn with `MainMaterial`, instanced or not: sun with soft
+// shadows from the neighbouring blocks in `box_texture`, sky with block occlusion, bounce
+// light off the team halves and sky reflections.
+// map xy: world position of the map's minimum corner, z: cell size
+fn main_lighting(
+    box_texture: texture_2d<f32>,
+    map: vec4<f32>,
+    camera: vec3<f32>,
+    pos: vec3<f32>,
+    nor: vec3<f32>,
+    bcl: vec3<f32>,
+) -> vec3<f32> {
+    let sun = normalize(vec3(sunx,suny,sunz));
+    let nds = dot(nor, sun);
+    let rd = -normalize(camera - pos);
+    let rfl = reflect(rd, nor);
+    let fre = pow(dot(rd, rfl) * 0.5 + 0.5, 5.0);
+    let softness = 12.0;
+    let scl = vec3(1.0, 0.8, 0.6);
+    let skc = vec3(0.6, 0.7, 1.0);
+    var sha = 1.0;
+    let cell_size = map.z;
+    let sca = (pos.xz - map.xy) / cell_size;
+    let c: vec2<i32> = vec2(i32(floor(sca.x)), i32(floor(sca.y)));
+    let sa = vec2<f32>(c) * cell_size + map.xy;
+    let h1 = load_height(box_texture, c + vec2(1, 1));
+    let h2 = load_height(box_texture, c + vec2(0, 1));
+    let h3 = load_height(box_texture, c + vec2(1, 0));
+    let re = sca - floor(sca);
+    let half_cell = cell_size * 0.5;
+    let sk = sky(vec3(rfl.x,abs(rfl.y),rfl.z));
+    //   h2 h1
+    // h5 c h3
+    //   h6
+    var occ = 1.0;
+    if pos.y != 0.0 {
+        occ = smoothstep(0.0, 0.05, pos.y);
+    }
+    if h1 >= 0.6 {
+        let box_height = h1 * 0.5;
+        let box_pos = vec3(sa.x + cell_size * 1.5, box_height, sa.y + cell_size * 1.5);
+        sha = min(sha, boxSoftShadow(pos - box_pos, sun, vec3(half_cell, box_height, half_cell), softness));
+        if pos.y == 0.0 {
+            occ *= smoothstep(1.0, 0.9, min(re.x, re.y));
+        }
+    }
+    if h2 >= 0.6 {
+        let box_height = h2 * 0.5;
+        let box_pos = vec3(sa.x + cell_size * 0.5, box_height, sa.y + cell_size * 1.5);
+        sha = min(sha, boxSoftShadow(pos - box_pos, sun, vec3(half_cell, box_height, half_cell), softness));
+        if pos.y == 0.0 {
+            occ *= smoothstep(1.0, 0.9, re.y);
+        }
+    }
+
+    if h3 >= 0.6 {
+        let box_height = h3 * 0.5;
+        let box_pos = vec3(sa.x + cell_size * 1.5, box_height, sa.y + cell_size * 0.5);
+        sha = min(sha, boxSoftShadow(pos - box_pos, sun, vec3(half_cell, box_height, half_cell), softness));
+        if pos.y == 0.0 {
+            occ *= smoothstep(1.0, 0.9, re.x);
+        }
+    }
+    occ = occ * 0.4 + 0.6;
+    let spc = pow((dot(rfl, sun) * 0.5 + 0.5) * fre, 9.0);
+    let bou = mix(
+        vec3(1.0, 0.0, 0.0),
+        vec3(0.0, 0.0, 1.0),
+        smoothstep(1.0, -1.0, pos.x)
+    );
+    return bcl * (
+    // sun
+    sha * scl * 2.0 * (max(0.0, nds) + 
+    //sky
+    spc * 10.0) + skc * occ * (0.7 + fre) * (dot(nor, vec3(0.0, 1.0, 0.0)) * 0.25 + 0.75) + 
+    //bounc
+    bou * 2.0 * max((-nds * 0.5 + 0.5), dot(nor, sun * vec3(1.0, -1.0, 1.0))) * max(0.0, 1. - pos.y) * (1. + fre) + 
+    //spec
+    sk * occ * (1.0 + fre) * 0.5
+    );
+}
+
 fn length2(v: vec3<f32>) -> f32 { return dot(v, v); }
 fn segShadow(ro: vec3<f32>, rd: vec3<f32>, pa: vec3<f32>, sh: f32) -> f32 {
     var sh = sh;
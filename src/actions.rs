@@ -0,0 +1,422 @@
+//! Player actions, decoupled from the devices producing them. Gameplay reads [`ActionState`],
+//! which is filled each frame from the keyboard, mouse and gamepads through the [`Bindings`]
+//! in effect. Those come from `assets/config/player.input.ron`, loaded through the asset
+//! server so edits are picked up while the game runs, and can also be changed in code by
+//! mutating [`Bindings::config`].
+
+use anyhow::{bail, Result};
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    input::{mouse::MouseMotion, InputSystem},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::{BoxedFuture, HashMap},
+};
+use serde::{Deserialize, Serialize};
+
+/// Where the bindings are loaded from, relative to the assets folder.
+const BINDINGS_PATH: &str = "config/player.input.ron";
+
+/// How far an axis has to be pushed for its action to count as pressed.
+const PRESS_THRESHOLD: f32 = 0.5;
+
+pub struct ActionsPlugin;
+impl Plugin for ActionsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<InputConfig>()
+            .init_asset_loader::<InputConfigLoader>()
+            .init_resource::<Bindings>()
+            .init_resource::<ActionState>()
+            .add_startup_system(load_bindings)
+            .add_system(reload_bindings)
+            .add_system(
+                update_actions
+                    .in_base_set(CoreSet::PreUpdate)
+                    .after(InputSystem),
+            );
+    }
+}
+
+/// Something the player can do, whichever device it comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    /// Axis, forward is positive.
+    MoveForward,
+    /// Axis, right is positive.
+    Strafe,
+    Jump,
+    Sprint,
+    /// Slides instead when already running fast.
+    Crouch,
+    Fire,
+    /// Switch straight to one weapon, see [`crate::weapon::LOADOUT`].
+    SelectRifle,
+    SelectRocketLauncher,
+    SelectGrenadeLauncher,
+    /// Step through the loadout, for devices without a button per weapon.
+    NextWeapon,
+    PreviousWeapon,
+    /// Leaves play for the pause menu, or goes back from it.
+    Pause,
+    /// Starts playing from the menu or resumes from pause.
+    Confirm,
+    /// Two axes turning the view, only bound to [`InputSource::MouseMotion`] and
+    /// [`InputSource::GamepadStick`].
+    Look,
+}
+
+/// A physical input an [`Action`] can be bound to.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum InputSource {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    MouseMotion,
+    GamepadButton(GamepadButtonType),
+    GamepadAxis(GamepadAxisType),
+    /// The horizontal and vertical axis of a stick.
+    GamepadStick(GamepadAxisType, GamepadAxisType),
+}
+
+impl InputSource {
+    fn is_2d(&self) -> bool {
+        matches!(
+            self,
+            InputSource::MouseMotion | InputSource::GamepadStick(..)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Binding {
+    pub action: Action,
+    pub source: InputSource,
+    /// Multiplies the source's value, negative to bind the back half of an axis or to invert
+    /// one.
+    #[serde(default = "one")]
+    pub scale: f32,
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+/// Sensitivities and bindings read from an `.input.ron` file, for example:
+///
+/// ```ron
+/// (
+///     mouse_sensitivity: 0.01,
+///     stick_sensitivity: 3.0,
+///     invert_y: false,
+///     bindings: [
+///         (action: MoveForward, source: Key(W)),
+///         (action: MoveForward, source: Key(S), scale: -1.0),
+///         (action: Look, source: GamepadStick(RightStickX, RightStickY)),
+///     ],
+/// )
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TypeUuid)]
+#[uuid = "5f0e7c1d-93a4-4b1e-8d2c-2a7f4e6b9c13"]
+pub struct InputConfig {
+    /// Radians the view turns per pixel of mouse movement.
+    pub mouse_sensitivity: f32,
+    /// Radians per second the view turns with a stick held all the way over.
+    pub stick_sensitivity: f32,
+    #[serde(default)]
+    pub invert_y: bool,
+    pub bindings: Vec<Binding>,
+}
+
+impl InputConfig {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let config: Self = ron::de::from_bytes(bytes)?;
+        for binding in &config.bindings {
+            if (binding.action == Action::Look) != binding.source.is_2d() {
+                bail!(
+                    "{:?} can't be bound to {:?}",
+                    binding.action,
+                    binding.source
+                );
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn to_ron(&self) -> Result<String> {
+        Ok(ron::ser::to_string_pretty(
+            self,
+            ron::ser::PrettyConfig::default(),
+        )?)
+    }
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        use Action::*;
+        use InputSource::*;
+        let bind = |action, source, scale| Binding {
+            action,
+            source,
+            scale,
+        };
+        Self {
+            mouse_sensitivity: 0.01,
+            stick_sensitivity: 3.0,
+            invert_y: false,
+            bindings: vec![
+                bind(MoveForward, Key(KeyCode::W), 1.0),
+                bind(MoveForward, Key(KeyCode::S), -1.0),
+                bind(Strafe, Key(KeyCode::D), 1.0),
+                bind(Strafe, Key(KeyCode::A), -1.0),
+                bind(Jump, Key(KeyCode::Space), 1.0),
+                bind(Sprint, Key(KeyCode::LShift), 1.0),
+                bind(Sprint, Key(KeyCode::RShift), 1.0),
+                bind(Crouch, Key(KeyCode::LControl), 1.0),
+                bind(Crouch, Key(KeyCode::C), 1.0),
+                bind(Fire, Mouse(MouseButton::Left), 1.0),
+                bind(SelectRifle, Key(KeyCode::Key1), 1.0),
+                bind(SelectRocketLauncher, Key(KeyCode::Key2), 1.0),
+                bind(SelectGrenadeLauncher, Key(KeyCode::Key3), 1.0),
+                bind(Pause, Key(KeyCode::Escape), 1.0),
+                bind(Confirm, Key(KeyCode::Return), 1.0),
+                bind(Confirm, Mouse(MouseButton::Left), 1.0),
+                bind(Look, MouseMotion, 1.0),
+                bind(MoveForward, GamepadAxis(GamepadAxisType::LeftStickY), 1.0),
+                bind(Strafe, GamepadAxis(GamepadAxisType::LeftStickX), 1.0),
+                bind(Jump, GamepadButton(GamepadButtonType::South), 1.0),
+                bind(Sprint, GamepadButton(GamepadButtonType::LeftThumb), 1.0),
+                bind(Crouch, GamepadButton(GamepadButtonType::East), 1.0),
+                bind(Fire, GamepadButton(GamepadButtonType::RightTrigger2), 1.0),
+                bind(
+                    NextWeapon,
+                    GamepadButton(GamepadButtonType::RightTrigger),
+                    1.0,
+                ),
+                bind(
+                    PreviousWeapon,
+                    GamepadButton(GamepadButtonType::LeftTrigger),
+                    1.0,
+                ),
+                bind(Pause, GamepadButton(GamepadButtonType::Start), 1.0),
+                bind(Confirm, GamepadButton(GamepadButtonType::South), 1.0),
+                bind(
+                    Look,
+                    GamepadStick(GamepadAxisType::RightStickX, GamepadAxisType::RightStickY),
+                    1.0,
+                ),
+            ],
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct InputConfigLoader;
+
+impl AssetLoader for InputConfigLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<()>> {
+        Box::pin(async move {
+            load_context.set_default_asset(LoadedAsset::new(InputConfig::parse(bytes)?));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["input.ron"]
+    }
+}
+
+/// The bindings in effect, the defaults until the config file has loaded.
+#[derive(Resource, Default)]
+pub struct Bindings {
+    pub file: Handle<InputConfig>,
+    pub config: InputConfig,
+}
+
+/// What the player is doing this frame, see [`Action`].
+#[derive(Resource, Debug, Default)]
+pub struct ActionState {
+    buttons: Input<Action>,
+    axes: HashMap<Action, f32>,
+    look: Vec2,
+}
+
+impl ActionState {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.buttons.pressed(action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.buttons.just_pressed(action)
+    }
+
+    /// How far an axis is pushed from -1 to 1, or 1 for a held button.
+    pub fn value(&self, action: Action) -> f32 {
+        self.axes.get(&action).copied().unwrap_or(0.0)
+    }
+
+    /// Radians to turn the view by this frame, to the left then upwards.
+    pub fn look(&self) -> Vec2 {
+        self.look
+    }
+
+    /// Set an action as if its inputs were pushed to `value`, for driving the game without
+    /// devices. Holds until the next update from real input.
+    pub fn set(&mut self, action: Action, value: f32) {
+        let value = value.clamp(-1.0, 1.0);
+        self.axes.insert(action, value);
+        if value.abs() > PRESS_THRESHOLD {
+            self.buttons.press(action);
+        } else {
+            self.buttons.release(action);
+        }
+    }
+
+    pub fn set_look(&mut self, look: Vec2) {
+        self.look = look;
+    }
+}
+
+fn load_bindings(mut bindings: ResMut<Bindings>, asset_server: Res<AssetServer>) {
+    bindings.file = asset_server.load(BINDINGS_PATH);
+}
+
+fn reload_bindings(
+    mut events: EventReader<AssetEvent<InputConfig>>,
+    mut bindings: ResMut<Bindings>,
+    configs: Res<Assets<InputConfig>>,
+) {
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle }
+                if *handle == bindings.file =>
+            {
+                let Some(config) = configs.get(handle) else {
+                    continue;
+                };
+                bindings.config = config.clone();
+                info!("loaded {} input bindings", config.bindings.len());
+            }
+            _ => {}
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_actions(
+    mut actions: ResMut<ActionState>,
+    bindings: Res<Bindings>,
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    mut mouse_motion: EventReader<MouseMotion>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+) {
+    let config = &bindings.config;
+    let motion: Vec2 = mouse_motion.iter().map(|ev| ev.delta).sum();
+    let axis = |axis_type| -> f32 {
+        gamepads
+            .iter()
+            .filter_map(|gamepad| gamepad_axes.get(GamepadAxis::new(gamepad, axis_type)))
+            .sum()
+    };
+
+    let mut axes = HashMap::<Action, f32>::default();
+    let mut look = Vec2::ZERO;
+    for binding in &config.bindings {
+        let value = match binding.source {
+            InputSource::Key(key) => keys.pressed(key) as u8 as f32,
+            InputSource::Mouse(button) => mouse_buttons.pressed(button) as u8 as f32,
+            InputSource::GamepadButton(button_type) => gamepads
+                .iter()
+                .any(|gamepad| gamepad_buttons.pressed(GamepadButton::new(gamepad, button_type)))
+                as u8 as f32,
+            InputSource::GamepadAxis(axis_type) => axis(axis_type),
+            // Mouse moving right and down turns right and down
+            InputSource::MouseMotion => {
+                look -= motion * config.mouse_sensitivity * binding.scale;
+                continue;
+            }
+            InputSource::GamepadStick(x, y) => {
+                let stick = Vec2::new(-axis(x), axis(y));
+                look += stick * config.stick_sensitivity * time.delta_seconds() * binding.scale;
+                continue;
+            }
+        };
+        *axes.entry(binding.action).or_default() += value * binding.scale;
+    }
+    if config.invert_y {
+        look.y = -look.y;
+    }
+
+    actions.buttons.clear();
+    for action in [
+        Action::MoveForward,
+        Action::Strafe,
+        Action::Jump,
+        Action::Sprint,
+        Action::Crouch,
+        Action::Fire,
+        Action::SelectRifle,
+        Action::SelectRocketLauncher,
+        Action::SelectGrenadeLauncher,
+        Action::NextWeapon,
+        Action::PreviousWeapon,
+        Action::Pause,
+        Action::Confirm,
+    ] {
+        actions.set(action, axes.get(&action).copied().unwrap_or(0.0));
+    }
+    actions.look = look;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_bindings_round_trip() {
+        let config = InputConfig::default();
+        let ron = config.to_ron().unwrap();
+        assert_eq!(InputConfig::parse(ron.as_bytes()).unwrap(), config);
+    }
+
+    #[test]
+    fn shipped_bindings_are_the_defaults() {
+        let file = include_bytes!("../assets/config/player.input.ron");
+        assert_eq!(InputConfig::parse(file).unwrap(), InputConfig::default());
+    }
+
+    #[test]
+    fn scale_defaults_to_one() {
+        let config = InputConfig::parse(
+            b"(mouse_sensitivity: 0.02, stick_sensitivity: 2.0, bindings: [
+                (action: Jump, source: Key(Up)),
+                (action: MoveForward, source: GamepadAxis(LeftStickY), scale: -1.0),
+            ])",
+        )
+        .unwrap();
+        assert!(!config.invert_y);
+        assert_eq!(config.bindings[0].scale, 1.0);
+        assert_eq!(config.bindings[1].scale, -1.0);
+    }
+
+    #[test]
+    fn look_only_binds_to_two_axes() {
+        let mismatched = [
+            "(action: Look, source: Key(W))",
+            "(action: Look, source: GamepadAxis(RightStickX))",
+            "(action: Fire, source: MouseMotion)",
+            "(action: Strafe, source: GamepadStick(LeftStickX, LeftStickY))",
+        ];
+        for binding in mismatched {
+            let config =
+                format!("(mouse_sensitivity: 0.01, stick_sensitivity: 3.0, bindings: [{binding}])");
+            assert!(InputConfig::parse(config.as_bytes()).is_err(), "{binding}");
+        }
+    }
+}
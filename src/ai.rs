@@ -0,0 +1,313 @@
+//! Critters that walk the arena on their own. A [`Brain`] picks a mood from how close the
+//! player is and how hurt it is, plans a path to match over the block grid and steers along
+//! it through the same [`MoveInput`] the player uses, so gait and physics behave identically.
+
+use bevy::{math::Vec3Swizzles, prelude::*};
+use rand::{thread_rng, Rng};
+
+use crate::{
+    collision::Collider,
+    critter::{make_cirtter, CritterSpec},
+    health::Health,
+    main_material::MainMaterial,
+    map::{Arena, MapFile},
+    movement::Movement,
+    nav::{find_path, is_open, Cell},
+    scene::SceneData,
+    sim::{MainPlayer, MoveInput, Physics},
+    team::Team,
+};
+
+/// Seconds between path updates while the target keeps moving.
+const REPATH_SECONDS: f32 = 0.5;
+/// How far from its current cell a wandering critter picks its next goal, in cells.
+const WANDER_CELLS: isize = 6;
+/// Random cells considered when looking for somewhere to flee to.
+const FLEE_SAMPLES: usize = 12;
+/// Radians per second critters turn to face where they're going.
+const TURN_RATE: f32 = 6.0;
+/// How much of its team's floor color a critter takes on.
+const TEAM_TINT: f32 = 0.6;
+/// Critter color before the team tint.
+const CRITTER_COLOR: Vec3 = Vec3::splat(0.9);
+
+pub struct AiPlugin;
+impl Plugin for AiPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(CritterSpawner {
+            count: 4,
+            delay: Timer::from_seconds(3.0, TimerMode::Repeating),
+        })
+        .add_startup_system(setup.in_base_set(StartupSet::PostStartup))
+        .add_system(tint_critters)
+        .add_systems((spawn_critters, think, steer).chain());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect, FromReflect)]
+pub enum Mood {
+    Wander,
+    Chase,
+    Flee,
+}
+
+#[derive(Debug, Component, Reflect)]
+pub struct Brain {
+    pub mood: Mood,
+    /// Distance the player is noticed from.
+    pub sight: f32,
+    /// Fraction of max health below which the critter runs instead of chasing.
+    pub flee_health: f32,
+    /// Strength of the move input, 1 is as fast as the player walks.
+    pub speed: f32,
+    /// World space xz waypoints still to visit, the next one first.
+    path: Vec<Vec2>,
+    repath: f32,
+}
+
+impl Default for Brain {
+    fn default() -> Self {
+        Self {
+            mood: Mood::Wander,
+            sight: 6.0,
+            flee_health: 0.3,
+            speed: 0.6,
+            path: Vec::new(),
+            repath: 0.0,
+        }
+    }
+}
+
+/// Keeps `count` critters of the player's opponents in the arena, bringing in a new one every
+/// `delay`.
+#[derive(Resource)]
+pub struct CritterSpawner {
+    pub count: usize,
+    pub delay: Timer,
+}
+
+#[derive(Resource)]
+struct CritterAssets {
+    blue: Handle<MainMaterial>,
+    red: Handle<MainMaterial>,
+}
+
+impl CritterAssets {
+    fn material(&self, team: Team) -> &Handle<MainMaterial> {
+        match team {
+            Team::Blue => &self.blue,
+            Team::Red => &self.red,
+        }
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<MainMaterial>>,
+    arena: Res<Arena>,
+    data: Res<SceneData>,
+) {
+    let mut material = || {
+        materials.add(MainMaterial {
+            color: Color::rgb(CRITTER_COLOR.x, CRITTER_COLOR.y, CRITTER_COLOR.z),
+            boxes: Some(arena.box_texture.clone()),
+            map: data.shader_params(),
+        })
+    };
+    commands.insert_resource(CritterAssets {
+        blue: material(),
+        red: material(),
+    });
+}
+
+/// Color each side's critters after the floor of their half whenever the map (re)loads.
+fn tint_critters(
+    mut events: EventReader<AssetEvent<MapFile>>,
+    arena: Res<Arena>,
+    maps: Res<Assets<MapFile>>,
+    assets: Res<CritterAssets>,
+    mut materials: ResMut<Assets<MainMaterial>>,
+) {
+    let loaded = events.iter().any(|event| match event {
+        AssetEvent::Created { handle } | AssetEvent::Modified { handle } => *handle == arena.map,
+        AssetEvent::Removed { .. } => false,
+    });
+    let Some(map) = maps.get(&arena.map).filter(|_| loaded) else {
+        return;
+    };
+    for team in [Team::Blue, Team::Red] {
+        let (Some(half), Some(material)) =
+            (map.half(team), materials.get_mut(assets.material(team)))
+        else {
+            continue;
+        };
+        let [r, g, b] = CRITTER_COLOR
+            .lerp(Vec3::from(half.color), TEAM_TINT)
+            .to_array();
+        material.color = Color::rgb(r, g, b);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_critters(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut spawner: ResMut<CritterSpawner>,
+    assets: Res<CritterAssets>,
+    arena: Res<Arena>,
+    maps: Res<Assets<MapFile>>,
+    data: Res<SceneData>,
+    player: Res<MainPlayer>,
+    teams: Query<&Team>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    brains: Query<(), With<Brain>>,
+) {
+    if !spawner.delay.tick(time.delta()).just_finished() || brains.iter().count() >= spawner.count {
+        return;
+    }
+    // Critters only know how to go after the player, so they always play against them
+    let team = teams
+        .get(player.id)
+        .map_or(Team::Red, |team| team.opponent());
+    let Some(position) = maps
+        .get(&arena.map)
+        .and_then(|map| map.spawn_point(team, &data, &mut thread_rng()))
+    else {
+        return;
+    };
+    let (spec, health) = match thread_rng().gen_range(0..3) {
+        0 => (CritterSpec::scout(), 30.0),
+        1 => (CritterSpec::soldier(), 50.0),
+        _ => (CritterSpec::brute(), 100.0),
+    };
+    let body = make_cirtter(
+        &mut commands,
+        spec,
+        assets.material(team).clone(),
+        &mut meshes,
+    );
+    commands
+        .spawn((
+            Brain::default(),
+            Physics::at(position),
+            Collider::default(),
+            MoveInput::default(),
+            Movement::default(),
+            Health::new(health),
+            team,
+            TransformBundle::from_transform(Transform::from_translation(position)),
+            VisibilityBundle::default(),
+        ))
+        .add_child(body);
+}
+
+fn think(
+    time: Res<Time>,
+    data: Res<SceneData>,
+    player: Res<MainPlayer>,
+    bodies: Query<&Physics>,
+    mut brains: Query<(&mut Brain, &Physics, &Health)>,
+) {
+    let Ok(player_position) = bodies.get(player.id).map(|physics| physics.position.xz()) else {
+        return;
+    };
+    let mut rng = thread_rng();
+    for (mut brain, physics, health) in brains.iter_mut() {
+        let position = physics.position.xz();
+        let mood = if position.distance(player_position) > brain.sight {
+            Mood::Wander
+        } else if health.current < health.max * brain.flee_health {
+            Mood::Flee
+        } else {
+            Mood::Chase
+        };
+        if mood != brain.mood {
+            brain.mood = mood;
+            brain.path.clear();
+            brain.repath = 0.0;
+        }
+
+        brain.repath -= time.delta_seconds();
+        // Wandering goals don't move, only pick a new one once the old one is reached
+        let stale = match brain.mood {
+            Mood::Wander => brain.path.is_empty(),
+            Mood::Chase | Mood::Flee => brain.repath <= 0.0,
+        };
+        let Some(start) = data.cell_at(position) else {
+            continue;
+        };
+        if !stale {
+            continue;
+        }
+        brain.repath = REPATH_SECONDS;
+        let goal = match brain.mood {
+            Mood::Chase => data.cell_at(player_position),
+            Mood::Wander => Some(random_cell(&data, start, &mut rng)),
+            Mood::Flee => (0..FLEE_SAMPLES)
+                .map(|_| random_cell(&data, start, &mut rng))
+                .filter(|cell| is_open(&data, *cell))
+                .max_by(|a, b| {
+                    let a = data.cell_center(a.0, a.1).distance(player_position);
+                    let b = data.cell_center(b.0, b.1).distance(player_position);
+                    a.total_cmp(&b)
+                }),
+        };
+        brain.path = goal
+            .and_then(|goal| find_path(&data, start, goal))
+            .map(|path| {
+                path.into_iter()
+                    // The first cell is the one we're already in
+                    .skip(1)
+                    .map(|(x, z)| data.cell_center(x, z))
+                    .collect()
+            })
+            .unwrap_or_default();
+    }
+}
+
+fn random_cell(data: &SceneData, (x, z): Cell, rng: &mut impl Rng) -> Cell {
+    let x = (x as isize + rng.gen_range(-WANDER_CELLS..=WANDER_CELLS))
+        .clamp(0, data.width() as isize - 1);
+    let z = (z as isize + rng.gen_range(-WANDER_CELLS..=WANDER_CELLS))
+        .clamp(0, data.depth() as isize - 1);
+    (x as usize, z as usize)
+}
+
+fn steer(
+    time: Res<Time>,
+    data: Res<SceneData>,
+    player: Res<MainPlayer>,
+    bodies: Query<&Physics, Without<Brain>>,
+    mut brains: Query<(&mut Brain, &Physics, &mut MoveInput, &mut Transform)>,
+) {
+    let player_position = bodies.get(player.id).map(|physics| physics.position.xz());
+    for (mut brain, physics, mut input, mut transform) in brains.iter_mut() {
+        let position = physics.position.xz();
+        while brain
+            .path
+            .first()
+            .is_some_and(|next| next.distance(position) < data.cell_size() * 0.25)
+        {
+            brain.path.remove(0);
+        }
+        let target = match (brain.mood, brain.path.first(), player_position) {
+            (_, Some(next), _) => Some(*next),
+            // Close enough that the path ran out, go straight for them
+            (Mood::Chase, None, Ok(player_position)) => Some(player_position),
+            _ => None,
+        };
+        let Some(target) = target else {
+            input.direction = Vec3::ZERO;
+            continue;
+        };
+        let direction = (target - position).normalize_or_zero();
+        input.direction = direction.extend(0.0).xzy() * brain.speed;
+        input.sprint = brain.mood == Mood::Flee;
+
+        if direction != Vec2::ZERO {
+            let facing = Quat::from_rotation_y((-direction.x).atan2(-direction.y));
+            let turn = (time.delta_seconds() * TURN_RATE).min(1.0);
+            transform.rotation = transform.rotation.slerp(facing, turn);
+        }
+    }
+}
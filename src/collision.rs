@@ -0,0 +1,536 @@
+//! Swept AABB collision and raycasts against the arena heightfield.
+//!
+//! Every block is a box from the floor up to its height, so a moving box is resolved one axis
+//! at a time: vertical first so landing on a block top wins over its sides, then x and z, each
+//! clamped to the nearest face in its path. Clamping one axis leaves the others free, which is
+//! what makes the player slide along walls.
+
+use bevy::{
+    math::{vec2, vec3, Vec3Swizzles},
+    prelude::*,
+};
+
+use crate::scene::SceneData;
+
+/// Gap left between touching faces so a box resting on a face isn't counted as overlapping it.
+const SKIN: f32 = 1e-4;
+
+/*
+        ||------||  --
+        ||      ||   |
+        ||      ||   | height
+        ||      ||   |
+        ||---*--||  --  * = Transform::translation (the feet)
+        |---|
+        radius
+*/
+#[derive(Debug, Clone, Copy, Component, Reflect)]
+pub struct Collider {
+    /// Half the width of the box on x and z.
+    pub radius: f32,
+    pub height: f32,
+    /// Ledges up to this high are stepped onto instead of blocking.
+    pub step_height: f32,
+}
+
+impl Default for Collider {
+    fn default() -> Self {
+        Self {
+            radius: 0.2,
+            height: 0.6,
+            step_height: 0.3,
+        }
+    }
+}
+
+impl Collider {
+    fn bounds(&self, feet: Vec3) -> (Vec3, Vec3) {
+        (
+            feet - vec3(self.radius, 0.0, self.radius),
+            feet + vec3(self.radius, self.height, self.radius),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sweep {
+    pub position: Vec3,
+    /// Axes the motion was cut short on, their velocity should be dropped.
+    pub blocked: BVec3,
+    /// The box came to rest on the floor or a block top.
+    pub on_ground: bool,
+}
+
+/// Move a collider at `start` by `motion`, stopping at the floor and block faces.
+pub fn move_and_collide(data: &SceneData, collider: &Collider, start: Vec3, motion: Vec3) -> Sweep {
+    let mut position = start;
+    let mut blocked = [false; 3];
+
+    let dy = sweep_axis(data, collider, position, 1, motion.y);
+    position.y += dy;
+    blocked[1] = dy != motion.y;
+    let on_ground = blocked[1] && motion.y <= 0.0;
+
+    for axis in [0, 2] {
+        let distance = motion[axis];
+        let moved = sweep_axis(data, collider, position, axis, distance);
+        if moved == distance {
+            position[axis] += moved;
+            continue;
+        }
+        // Blocked, see if the obstacle is low enough to step onto
+        if on_ground {
+            let lifted = position + Vec3::Y * collider.step_height;
+            let lifted_moved = sweep_axis(data, collider, lifted, axis, distance);
+            if lifted_moved.abs() > moved.abs() {
+                let mut stepped = lifted;
+                stepped[axis] += lifted_moved;
+                stepped.y += sweep_axis(data, collider, stepped, 1, -collider.step_height);
+                position = stepped;
+                blocked[axis] = lifted_moved != distance;
+                continue;
+            }
+        }
+        position[axis] += moved;
+        blocked[axis] = true;
+    }
+
+    Sweep {
+        position,
+        blocked: BVec3::new(blocked[0], blocked[1], blocked[2]),
+        on_ground,
+    }
+}
+
+/// Outward normal of a block side within `reach` of a collider at `feet`, ignoring ledges low
+/// enough to step onto.
+pub fn wall_normal(data: &SceneData, collider: &Collider, feet: Vec3, reach: f32) -> Option<Vec3> {
+    let lifted = feet + Vec3::Y * collider.step_height;
+    [(0, reach), (0, -reach), (2, reach), (2, -reach)]
+        .into_iter()
+        .find(|&(axis, distance)| sweep_axis(data, collider, lifted, axis, distance) != distance)
+        .map(|(axis, distance)| {
+            let mut normal = Vec3::ZERO;
+            normal[axis] = -distance.signum();
+            normal
+        })
+}
+
+/// How far along `axis` the collider can move before touching the floor or a block.
+fn sweep_axis(
+    data: &SceneData,
+    collider: &Collider,
+    feet: Vec3,
+    axis: usize,
+    distance: f32,
+) -> f32 {
+    if distance == 0.0 {
+        return 0.0;
+    }
+    let (min, max) = collider.bounds(feet);
+    let mut offset = Vec3::ZERO;
+    offset[axis] = distance;
+    let mut allowed = distance;
+    if axis == 1 && distance < 0.0 {
+        allowed = allowed.max(-min.y);
+    }
+    for (block_min, block_max) in blocks_in(data, min.min(min + offset), max.max(max + offset)) {
+        let overlaps = (0..3)
+            .filter(|a| *a != axis)
+            .all(|a| min[a] < block_max[a] - SKIN && max[a] > block_min[a] + SKIN);
+        if !overlaps {
+            continue;
+        }
+        if distance > 0.0 && block_min[axis] >= max[axis] - SKIN {
+            allowed = allowed.min(block_min[axis] - max[axis]);
+        } else if distance < 0.0 && block_max[axis] <= min[axis] + SKIN {
+            allowed = allowed.max(block_max[axis] - min[axis]);
+        }
+    }
+    if distance > 0.0 {
+        allowed.max(0.0)
+    } else {
+        allowed.min(0.0)
+    }
+}
+
+/// Bounds of every block whose cell touches the xz extent of `min..max`.
+fn blocks_in(data: &SceneData, min: Vec3, max: Vec3) -> impl Iterator<Item = (Vec3, Vec3)> + '_ {
+    let lo = data.world_to_grid(min.xz()).floor();
+    let hi = data.world_to_grid(max.xz()).floor();
+    let cell_size = data.cell_size();
+    (lo.y as isize..=hi.y as isize)
+        .flat_map(move |z| (lo.x as isize..=hi.x as isize).map(move |x| (x, z)))
+        .filter_map(move |(x, z)| {
+            let height = data.block_height(x, z)?;
+            let corner = data.grid_to_world(vec2(x as f32, z as f32));
+            Some((
+                vec3(corner.x, 0.0, corner.y),
+                vec3(corner.x + cell_size, height, corner.y + cell_size),
+            ))
+        })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    pub distance: f32,
+    pub point: Vec3,
+    pub normal: Vec3,
+    /// The block that was hit, `None` for the floor.
+    pub cell: Option<(usize, usize)>,
+}
+
+/// First block or floor hit by a ray, `direction` must be normalized.
+pub fn raycast(
+    data: &SceneData,
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+) -> Option<RayHit> {
+    let mut limit = max_distance;
+    let mut floor = None;
+    if direction.y < 0.0 && origin.y >= 0.0 {
+        let t = -origin.y / direction.y;
+        if t <= limit {
+            limit = t;
+            floor = Some(RayHit {
+                distance: t,
+                point: origin + direction * t,
+                normal: Vec3::Y,
+                cell: None,
+            });
+        }
+    }
+
+    // Walk the cells under the ray in order (Amanatides & Woo), blocks never leave their
+    // cell so the first one hit is the nearest
+    let cell_size = data.cell_size();
+    let start = data.world_to_grid(origin.xz());
+    let dir = direction.xz() / cell_size;
+    let step = dir.signum();
+    let t_delta = dir.recip().abs();
+    let mut cell = start.floor();
+    let next_edge = |c: f32, s: f32, d: f32| match d {
+        d if d > 0.0 => (c + 1.0 - s) / d,
+        d if d < 0.0 => (c - s) / d,
+        _ => f32::INFINITY,
+    };
+    let mut t_max = vec2(
+        next_edge(cell.x, start.x, dir.x),
+        next_edge(cell.y, start.y, dir.y),
+    );
+    let mut t = 0.0;
+    while t <= limit {
+        let (x, z) = (cell.x as isize, cell.y as isize);
+        if let Some(height) = data.block_height(x, z) {
+            let corner = data.grid_to_world(cell);
+            let min = vec3(corner.x, 0.0, corner.y);
+            let max = vec3(corner.x + cell_size, height, corner.y + cell_size);
+            if let Some((distance, normal)) = ray_box(origin, direction, min, max) {
+                if distance <= limit {
+                    return Some(RayHit {
+                        distance,
+                        point: origin + direction * distance,
+                        normal,
+                        cell: Some((x as usize, z as usize)),
+                    });
+                }
+            }
+        }
+        if t_max.x.is_infinite() && t_max.y.is_infinite() {
+            break;
+        }
+        if t_max.x < t_max.y {
+            t = t_max.x;
+            t_max.x += t_delta.x;
+            cell.x += step.x;
+        } else {
+            t = t_max.y;
+            t_max.y += t_delta.y;
+            cell.y += step.y;
+        }
+    }
+    floor
+}
+
+/// Entry distance and face normal of a ray into an axis aligned box, if it enters ahead of
+/// the origin.
+fn ray_box(origin: Vec3, direction: Vec3, min: Vec3, max: Vec3) -> Option<(f32, Vec3)> {
+    let mut t_near = f32::NEG_INFINITY;
+    let mut t_far = f32::INFINITY;
+    let mut axis = Vec3::ZERO;
+    for i in 0..3 {
+        let (o, d) = (origin[i], direction[i]);
+        // Parallel to this pair of faces, dividing would give 0 * inf = NaN on a face plane
+        if d == 0.0 {
+            if o < min[i] || o > max[i] {
+                return None;
+            }
+            continue;
+        }
+        let t1 = (min[i] - o) / d;
+        let t2 = (max[i] - o) / d;
+        if t1.min(t2) > t_near {
+            t_near = t1.min(t2);
+            axis = Vec3::AXES[i];
+        }
+        t_far = t_far.min(t1.max(t2));
+    }
+    if t_near > t_far || t_near < 0.0 {
+        return None;
+    }
+    Some((t_near, -axis * direction.signum()))
+}
+
+// Adapted from https://iquilezles.org/articles/intersectors/
+/// Distance along a normalized ray to a capsule from `a` to `b`.
+pub fn ray_capsule(origin: Vec3, direction: Vec3, a: Vec3, b: Vec3, radius: f32) -> Option<f32> {
+    let ba = b - a;
+    let oa = origin - a;
+    let baba = ba.dot(ba);
+    let bard = ba.dot(direction);
+    let baoa = ba.dot(oa);
+    let qa = baba - bard * bard;
+    let qb = baba * direction.dot(oa) - baoa * bard;
+    let qc = baba * oa.dot(oa) - baoa * baoa - radius * radius * baba;
+    let h = qb * qb - qa * qc;
+    if h < 0.0 {
+        return None;
+    }
+    if qa > 0.0 {
+        let t = (-qb - h.sqrt()) / qa;
+        let y = baoa + t * bard;
+        if y > 0.0 && y < baba {
+            return (t >= 0.0).then_some(t);
+        }
+        // Missed the side so it can only hit the cap at the nearer end
+        let cap = if y <= 0.0 { a } else { b };
+        return ray_sphere(origin, direction, cap, radius);
+    }
+    // Parallel to the capsule, or it is just a sphere
+    [a, b]
+        .into_iter()
+        .filter_map(|cap| ray_sphere(origin, direction, cap, radius))
+        .reduce(f32::min)
+}
+
+/// Distance along a normalized ray to a sphere.
+pub fn ray_sphere(origin: Vec3, direction: Vec3, center: Vec3, radius: f32) -> Option<f32> {
+    let oc = origin - center;
+    let b = direction.dot(oc);
+    let h = b * b - oc.dot(oc) + radius * radius;
+    if h < 0.0 {
+        return None;
+    }
+    let t = -b - h.sqrt();
+    (t >= 0.0).then_some(t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 5x5 map with one cell per unit and its corner on the world origin.
+    fn grid(blocks: &[(usize, usize, f32)]) -> SceneData {
+        let mut data = SceneData::new(5, 5, 1.0, Vec2::ZERO);
+        for (x, z, height) in blocks {
+            data.set(*x, *z, *height);
+        }
+        data
+    }
+
+    #[test]
+    fn rays_hit_block_faces() {
+        let data = grid(&[(2, 2, 1.0)]);
+        let hit = raycast(&data, vec3(0.5, 0.5, 2.5), Vec3::X, 10.0).unwrap();
+        assert!((hit.distance - 1.5).abs() < 1e-5);
+        assert_eq!(hit.normal, -Vec3::X);
+        assert_eq!(hit.cell, Some((2, 2)));
+
+        let down = vec3(0.0, -1.0, 1.0).normalize();
+        let hit = raycast(&data, vec3(2.5, 3.0, 0.5), down, 10.0).unwrap();
+        assert!((hit.point - vec3(2.5, 1.0, 2.5)).length() < 1e-4);
+        assert_eq!(hit.normal, Vec3::Y);
+        assert_eq!(hit.cell, Some((2, 2)));
+    }
+
+    #[test]
+    fn axis_aligned_rays_hit_blocks() {
+        let data = grid(&[(2, 2, 1.0)]);
+        let hit = raycast(&data, vec3(2.5, 0.5, 0.5), Vec3::Z, 10.0).unwrap();
+        assert!((hit.distance - 1.5).abs() < 1e-5);
+        assert_eq!(hit.normal, -Vec3::Z);
+        assert_eq!(hit.cell, Some((2, 2)));
+        let hit = raycast(&data, vec3(4.5, 0.5, 2.5), -Vec3::X, 10.0).unwrap();
+        assert_eq!(hit.normal, Vec3::X);
+        assert_eq!(hit.cell, Some((2, 2)));
+        // Starting exactly on the plane of the block's side
+        let hit = raycast(&data, vec3(0.5, 0.5, 2.0), Vec3::X, 10.0).unwrap();
+        assert!((hit.distance - 1.5).abs() < 1e-5);
+        assert_eq!(hit.normal, -Vec3::X);
+    }
+
+    #[test]
+    fn rays_pass_over_low_blocks() {
+        let data = grid(&[(2, 2, 0.7)]);
+        assert_eq!(raycast(&data, vec3(0.5, 0.8, 2.5), Vec3::X, 10.0), None);
+    }
+
+    #[test]
+    fn rays_hit_the_floor() {
+        let data = grid(&[]);
+        let direction = vec3(1.0, -1.0, 0.0).normalize();
+        let hit = raycast(&data, vec3(0.5, 1.0, 0.5), direction, 10.0).unwrap();
+        assert!((hit.point - vec3(1.5, 0.0, 0.5)).length() < 1e-5);
+        assert_eq!(hit.normal, Vec3::Y);
+        assert_eq!(hit.cell, None);
+    }
+
+    #[test]
+    fn rays_stop_at_max_distance() {
+        let data = grid(&[(4, 2, 1.0)]);
+        assert_eq!(raycast(&data, vec3(0.5, 0.5, 2.5), Vec3::X, 3.0), None);
+        assert!(raycast(&data, vec3(0.5, 0.5, 2.5), Vec3::X, 4.0).is_some());
+        let direction = vec3(1.0, -1.0, 0.0).normalize();
+        assert_eq!(raycast(&data, vec3(0.5, 1.0, 0.5), direction, 1.0), None);
+    }
+
+    #[test]
+    fn rays_hit_capsules_and_spheres() {
+        let (a, b) = (Vec3::ZERO, vec3(0.0, 2.0, 0.0));
+        let side = ray_capsule(vec3(-2.0, 1.0, 0.0), Vec3::X, a, b, 0.5).unwrap();
+        assert!((side - 1.5).abs() < 1e-5);
+        let cap = ray_capsule(vec3(0.0, 5.0, 0.0), -Vec3::Y, a, b, 0.5).unwrap();
+        assert!((cap - 2.5).abs() < 1e-5);
+        assert_eq!(ray_capsule(vec3(-2.0, 1.0, 1.0), Vec3::X, a, b, 0.5), None);
+        assert_eq!(ray_capsule(vec3(2.0, 1.0, 0.0), Vec3::X, a, b, 0.5), None);
+
+        let hit = ray_sphere(vec3(-2.0, 0.0, 0.0), Vec3::X, Vec3::ZERO, 1.0).unwrap();
+        assert!((hit - 1.0).abs() < 1e-5);
+        assert_eq!(
+            ray_sphere(vec3(-2.0, 2.0, 0.0), Vec3::X, Vec3::ZERO, 1.0),
+            None
+        );
+        assert_eq!(
+            ray_sphere(vec3(2.0, 0.0, 0.0), Vec3::X, Vec3::ZERO, 1.0),
+            None
+        );
+    }
+
+    #[test]
+    fn lands_on_floor() {
+        let data = grid(&[]);
+        let sweep = move_and_collide(
+            &data,
+            &Collider::default(),
+            vec3(0.5, 0.3, 0.5),
+            vec3(0.0, -1.0, 0.0),
+        );
+        assert_eq!(sweep.position.y, 0.0);
+        assert!(sweep.on_ground);
+        assert!(sweep.blocked.y);
+    }
+
+    #[test]
+    fn lands_on_block_top() {
+        let data = grid(&[(2, 2, 1.0)]);
+        let sweep = move_and_collide(
+            &data,
+            &Collider::default(),
+            vec3(2.5, 1.5, 2.5),
+            vec3(0.0, -1.0, 0.0),
+        );
+        assert!((sweep.position.y - 1.0).abs() < 1e-5);
+        assert!(sweep.on_ground);
+    }
+
+    #[test]
+    fn walls_stop_motion_into_them() {
+        let data = grid(&[(2, 2, 1.0)]);
+        let collider = Collider::default();
+        let sweep = move_and_collide(&data, &collider, vec3(1.5, 0.0, 2.5), vec3(1.0, 0.0, 0.0));
+        assert!((sweep.position.x - (2.0 - collider.radius)).abs() < 1e-5);
+        assert!(sweep.blocked.x);
+    }
+
+    #[test]
+    fn slides_along_walls() {
+        let data = grid(&[(2, 1, 1.0), (2, 2, 1.0), (2, 3, 1.0)]);
+        let sweep = move_and_collide(
+            &data,
+            &Collider::default(),
+            vec3(1.5, 0.0, 1.5),
+            vec3(1.0, 0.0, 1.0),
+        );
+        assert!(sweep.blocked.x);
+        assert!(!sweep.blocked.z);
+        assert!((sweep.position.z - 2.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn fast_motion_does_not_tunnel() {
+        let data = grid(&[(2, 2, 1.0)]);
+        let sweep = move_and_collide(
+            &data,
+            &Collider::default(),
+            vec3(0.5, 0.0, 2.5),
+            vec3(4.0, 0.0, 0.0),
+        );
+        assert!(sweep.position.x < 2.0);
+    }
+
+    #[test]
+    fn steps_up_small_ledges() {
+        let data = grid(&[(1, 2, 0.8), (2, 2, 1.0)]);
+        let sweep = move_and_collide(
+            &data,
+            &Collider::default(),
+            vec3(1.5, 0.8, 2.5),
+            vec3(1.0, -0.01, 0.0),
+        );
+        assert!((sweep.position.y - 1.0).abs() < 1e-5);
+        assert!((sweep.position.x - 2.5).abs() < 1e-5);
+        assert!(!sweep.blocked.x);
+    }
+
+    #[test]
+    fn does_not_step_up_tall_blocks() {
+        let data = grid(&[(2, 2, 1.5)]);
+        let sweep = move_and_collide(
+            &data,
+            &Collider::default(),
+            vec3(1.5, 0.0, 2.5),
+            vec3(1.0, -0.01, 0.0),
+        );
+        assert_eq!(sweep.position.y, 0.0);
+        assert!(sweep.blocked.x);
+    }
+
+    #[test]
+    fn airborne_boxes_do_not_step() {
+        let data = grid(&[(1, 2, 0.8), (2, 2, 1.0)]);
+        let sweep = move_and_collide(
+            &data,
+            &Collider::default(),
+            vec3(1.5, 0.9, 2.5),
+            vec3(1.0, 0.05, 0.0),
+        );
+        assert!(sweep.blocked.x);
+        assert!(sweep.position.x < 2.0);
+    }
+
+    #[test]
+    fn finds_walls_within_reach() {
+        let data = grid(&[(2, 2, 1.0), (1, 3, 0.8)]);
+        let collider = Collider::default();
+        let next_to = vec3(2.0 - collider.radius - 0.01, 0.0, 2.5);
+        assert_eq!(
+            wall_normal(&data, &collider, next_to, 0.05),
+            Some(Vec3::NEG_X)
+        );
+        assert_eq!(wall_normal(&data, &collider, next_to, 0.005), None);
+        // Ledges low enough to step onto aren't walls
+        let by_ledge = vec3(1.5, 0.7, 3.0 - collider.radius - 0.01);
+        assert_eq!(wall_normal(&data, &collider, by_ledge, 0.05), None);
+    }
+}
@@ -1,11 +1,24 @@
-use std::{f32::consts::TAU, ops::Mul};
+use std::{
+    f32::consts::{PI, TAU},
+    ops::Mul,
+};
 
 use bevy::{
-    math::{vec2, vec3, Vec3Swizzles},
+    math::{vec2, vec3, Affine3A, Vec3Swizzles},
     prelude::*,
+    render::mesh::{Indices, VertexAttributeValues},
 };
 use itertools::Itertools;
-use rand::{thread_rng, Rng};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    gait::{gait_for_speed, GaitKind, GaitSpeed},
+    ik::{solve_chain, JointLimit},
+    scene::SceneData,
+    sim::CritterLegSet,
+};
 
 /*  ||=====================||  --             ||========||    /
     ||                     ||  |              ||        ||   /
@@ -30,39 +43,285 @@ use rand::{thread_rng, Rng};
 ================================================================================
 */
 
-const BODY_CLEAR: f32 = 0.2;
-// const BODY_HEIGHT: f32 = 0.2;
-// const BODY_LENGTH: f32 = 0.3;
-const BODY_WIDTH: f32 = 0.1;
-const R1: f32 = 0.2;
-const R2: f32 = 0.35;
-const LEG_WIDTH: f32 = 0.02;
-const NUM_LEGS: usize = 8;
+/// How quickly the body settles onto the plane of its feet, per second.
+const BODY_FOLLOW: f32 = 10.0;
+/// Spring pulling the body back to rest after it's been thrown around by acceleration.
+const SWAY_STIFFNESS: f32 = 120.0;
+/// Slightly under critically damped so the body overshoots a little before settling.
+const SWAY_DAMPING: f32 = 16.0;
+/// How far the body is pushed per unit of acceleration, before the spring pulls it back.
+const SWAY_INERTIA: f32 = 0.18;
+const MAX_SWAY: f32 = 0.05;
+/// Radians the body leans per unit of sway.
+const LEAN: f32 = 4.0;
+const BODY_SECTORS: u16 = 8;
+const BODY_STACKS: u16 = 5;
 
 pub struct CritterPlugin;
 impl Plugin for CritterPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(update_critter)
-            .add_system(coordinate_critter)
-            .add_system(update_critter_mesh);
+        // Legs are stepped by `GameSimPlugin`, the body and meshes follow them
+        app.add_system(update_critter_body.after(CritterLegSet))
+            .add_system(select_critter_detail)
+            .add_system(update_critter_mesh.after(select_critter_detail));
+    }
+}
+
+/// Randomness in how critters time their steps. Seeded from entropy, replace it with a fixed
+/// seed to make walking reproducible.
+#[derive(Resource)]
+pub struct CritterRng(pub ChaCha8Rng);
+
+impl Default for CritterRng {
+    fn default() -> Self {
+        Self(ChaCha8Rng::from_entropy())
+    }
+}
+
+/// How finely a critter's legs are meshed, picked each frame by how close it is to the nearest
+/// active camera. A critter has a single mesh drawn by every camera, so this is not per view.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Default, Reflect)]
+pub enum MeshDetail {
+    /// Three sided legs bent only at their joints.
+    Low,
+    /// Round legs curving smoothly between their joints.
+    #[default]
+    High,
+}
+
+/// Put on a camera to set how close critters have to be to it to get [`MeshDetail::High`].
+/// Cameras without one use the default. The detail is shared, so other cameras further away
+/// see the high detail mesh too.
+#[derive(Component, Debug, Clone, Reflect)]
+pub struct CritterLod {
+    pub high_detail_distance: f32,
+}
+
+impl Default for CritterLod {
+    fn default() -> Self {
+        Self {
+            high_detail_distance: 8.0,
+        }
+    }
+}
+
+/// How much further than `high_detail_distance` a detailed critter keeps its detail, so
+/// critters standing right at the edge don't flicker between meshes.
+const LOD_HYSTERESIS: f32 = 1.0;
+
+/// Sides around a high detail leg.
+const HLOD_SIDES: u16 = 6;
+/// Rings along each segment of a high detail leg.
+const HLOD_SPACERS: u16 = 4;
+
+/// The shape of a critter, see the diagram above for what the lengths measure.
+#[derive(Debug, Clone, Reflect, FromReflect, Serialize, Deserialize)]
+pub struct CritterSpec {
+    /// Number of legs, spread evenly around the body. Which of them step together is up to
+    /// the gait, see [`Gait::group`](crate::gait::Gait::group).
+    pub legs: usize,
+    /// Length of each leg segment from the body out to the foot, `R1` and `R2` for the
+    /// two segment legs drawn above.
+    pub segments: Vec<f32>,
+    /// How far each joint between two segments can bend, one per joint.
+    pub joint_limits: Vec<JointLimit>,
+    pub body_width: f32,
+    /// `BODY_CLEAR`, how high the body rides above its feet.
+    pub body_clear: f32,
+    /// Radii of the ellipse the legs are attached around, x across the body and y along it.
+    pub attachment: Vec2,
+    /// How far out from its attachment a resting foot stands.
+    pub reach: f32,
+    /// `LEG_WIDTH`
+    pub leg_width: f32,
+    /// How far a foot is allowed to drift from its resting spot before it steps.
+    pub stride: f32,
+    /// Steps per second at a standstill, moving faster speeds them up further.
+    pub gait_speed: f32,
+    /// Gaits to switch between as the critter speeds up.
+    pub gaits: Vec<GaitSpeed>,
+}
+
+/// Knees never lock straight, so the pole always decides which way they bend.
+const KNEE: JointLimit = JointLimit {
+    min_bend: 0.1,
+    max_bend: 2.8,
+};
+
+impl CritterSpec {
+    /// Length of a fully stretched leg.
+    pub fn leg_length(&self) -> f32 {
+        self.segments.iter().sum()
+    }
+
+    /// Small and quick on four long legs.
+    pub fn scout() -> Self {
+        Self {
+            legs: 4,
+            segments: vec![0.22, 0.4],
+            joint_limits: vec![KNEE],
+            body_width: 0.08,
+            attachment: vec2(0.036, 0.03),
+            reach: 0.25,
+            leg_width: 0.015,
+            stride: 0.5,
+            gait_speed: 9.0,
+            gaits: vec![
+                GaitSpeed {
+                    min_speed: 0.0,
+                    gait: GaitKind::Tripod,
+                },
+                GaitSpeed {
+                    min_speed: 2.0,
+                    gait: GaitKind::Gallop,
+                },
+            ],
+            ..default()
+        }
+    }
+
+    /// Six insect legs, each with a short coxa before the knee.
+    pub fn soldier() -> Self {
+        Self {
+            legs: 6,
+            segments: vec![0.08, 0.17, 0.32],
+            joint_limits: vec![
+                JointLimit {
+                    min_bend: 0.2,
+                    max_bend: 1.2,
+                },
+                JointLimit {
+                    min_bend: 0.3,
+                    max_bend: 2.4,
+                },
+            ],
+            gait_speed: 7.0,
+            gaits: vec![
+                GaitSpeed {
+                    min_speed: 0.0,
+                    gait: GaitKind::Wave,
+                },
+                GaitSpeed {
+                    min_speed: 0.3,
+                    gait: GaitKind::Ripple,
+                },
+                GaitSpeed {
+                    min_speed: 1.0,
+                    gait: GaitKind::Tripod,
+                },
+            ],
+            ..default()
+        }
+    }
+
+    /// Big, heavy and slow on eight thick legs.
+    pub fn brute() -> Self {
+        Self {
+            legs: 8,
+            segments: vec![0.3, 0.5],
+            joint_limits: vec![KNEE],
+            body_width: 0.16,
+            body_clear: 0.3,
+            attachment: vec2(0.072, 0.04),
+            reach: 0.3,
+            leg_width: 0.035,
+            stride: 0.5,
+            gait_speed: 4.5,
+            gaits: vec![
+                GaitSpeed {
+                    min_speed: 0.0,
+                    gait: GaitKind::Wave,
+                },
+                GaitSpeed {
+                    min_speed: 0.5,
+                    gait: GaitKind::Ripple,
+                },
+            ],
+        }
+    }
+}
+
+impl Default for CritterSpec {
+    fn default() -> Self {
+        Self {
+            legs: 8,
+            segments: vec![0.2, 0.35],
+            joint_limits: vec![KNEE],
+            body_width: 0.1,
+            body_clear: 0.2,
+            attachment: vec2(0.045, 0.025),
+            reach: 0.2,
+            leg_width: 0.02,
+            stride: 0.4,
+            gait_speed: 6.0,
+            gaits: vec![
+                GaitSpeed {
+                    min_speed: 0.0,
+                    gait: GaitKind::Ripple,
+                },
+                GaitSpeed {
+                    min_speed: 1.0,
+                    gait: GaitKind::Tripod,
+                },
+            ],
+        }
     }
 }
 
 #[derive(Debug, Component, Reflect)]
 pub struct Critter {
     pub velocity: Vec3,
-    legs: [CritterLeg; NUM_LEGS],
+    pub spec: CritterSpec,
+    legs: Vec<CritterLeg>,
+    /// Some legs are still partway through a step.
     moving: bool,
-    set_priorety: bool,
-    just_moved: bool,
+    gait: GaitKind,
+    /// The group of legs the gait steps next.
+    next_group: usize,
+    /// Change in `velocity` over the last fixed step, per second, see [`Critter::step_velocity`].
+    acceleration: Vec3,
+    /// Secondary motion currently added to the body's transform, in its parent's space.
+    sway: Vec3,
+    sway_velocity: Vec3,
+    lean: Quat,
 }
 
-#[derive(Debug, Reflect)]
+impl Critter {
+    /// The world space joints of every leg, from the body out to the foot.
+    pub fn leg_joints(&self) -> impl Iterator<Item = &[Vec3]> + '_ {
+        self.legs.iter().map(|leg| &leg.global_joints[..])
+    }
+
+    pub fn legs(&self) -> &[CritterLeg] {
+        &self.legs
+    }
+
+    pub fn gait(&self) -> GaitKind {
+        self.gait
+    }
+
+    /// Set the velocity at the end of a fixed step `delta` seconds long. The body sways against
+    /// the change every frame until the next step, however many frames that is.
+    pub fn step_velocity(&mut self, velocity: Vec3, delta: f32) {
+        self.acceleration = (velocity - self.velocity) / delta;
+        self.velocity = velocity;
+    }
+
+    /// Every leg bone in world space as a line segment, for hit tests.
+    pub fn bones(&self) -> impl Iterator<Item = (Vec3, Vec3)> + '_ {
+        self.leg_joints()
+            .flat_map(|joints| joints.windows(2).map(|bone| (bone[0], bone[1])))
+    }
+}
+
+#[derive(Debug, Reflect, FromReflect)]
 pub struct CritterLeg {
     local_body: Vec3,
     local_comfy_position: Vec3,
     comfy_distance: f32,
-    global_knee: Vec3,
+    /// Every joint from the body to the foot, where the foot is the closest the leg can get
+    /// to `global_foot`.
+    global_joints: Vec<Vec3>,
     global_body: Vec3,
     global_foot: Vec3,
     global_previous_target: Vec3,
@@ -71,70 +330,158 @@ pub struct CritterLeg {
     animation_speed: f32,
 }
 
+impl CritterLeg {
+    /// How far through its step the leg is, from 0 lifting off to 1 planted again.
+    pub fn step(&self) -> f32 {
+        self.t
+    }
+
+    /// Where the current step puts the foot down.
+    pub fn target(&self) -> Vec3 {
+        self.global_target
+    }
+
+    pub fn foot(&self) -> Vec3 {
+        self.global_foot
+    }
+
+    /// Every joint from the body out to the foot, the ones in between are the knees.
+    pub fn joints(&self) -> &[Vec3] {
+        &self.global_joints
+    }
+
+    /// On the ground rather than partway through a step.
+    pub fn planted(&self) -> bool {
+        self.t <= 0.0 || self.t >= 1.0
+    }
+}
+
 pub fn make_cirtter<T: Material>(
     commands: &mut Commands,
+    spec: CritterSpec,
     material: Handle<T>,
     meshes: &mut ResMut<Assets<Mesh>>,
 ) -> Entity {
-    let legs = (0..NUM_LEGS)
-        .map(|x| (x as f32 / NUM_LEGS as f32 + 0.5 / NUM_LEGS as f32) * TAU)
+    let legs = rest_legs(&spec);
+    let transform = Transform::from_translation(vec3(0.0, spec.body_clear, 0.0));
+    commands
+        .spawn((
+            MeshDetail::High,
+            MaterialMeshBundle {
+                mesh: meshes.add(make_hlod_critter_mesh(
+                    legs.iter().map(|leg| &leg.global_joints[..]),
+                    transform.into(),
+                    &spec,
+                )),
+                material,
+                transform,
+                ..default()
+            },
+            Critter {
+                legs,
+                gait: gait_for_speed(&spec.gaits, 0.0),
+                spec,
+                velocity: Vec3::ZERO,
+                moving: false,
+                next_group: 0,
+                acceleration: Vec3::ZERO,
+                sway: Vec3::ZERO,
+                sway_velocity: Vec3::ZERO,
+                lean: Quat::IDENTITY,
+            },
+        ))
+        .id()
+}
+
+/// Legs spread evenly around the body with their feet at rest, in the body's space.
+fn rest_legs(spec: &CritterSpec) -> Vec<CritterLeg> {
+    let num_legs = spec.legs;
+    (0..num_legs)
+        .map(|x| (x as f32 / num_legs as f32 + 0.5 / num_legs as f32) * TAU)
         .map(|x| x.sin_cos())
-        .map(|(x, y)| [vec2(x * 0.45, y * 0.25) * BODY_WIDTH, vec2(x, y * 2.0)])
+        .map(|(x, y)| [vec2(x, y) * spec.attachment, vec2(x, y * 2.0)])
         .map(|[pos, dir]| {
             let body = vec3(
-                pos.x * 0.5 + pos.x.signum() * BODY_WIDTH * 0.45,
+                pos.x * 0.5 + pos.x.signum() * spec.body_width * 0.45,
                 0.02,
                 pos.y,
             );
-            let foot = (dir.normalize_or_zero() * 0.2 + vec2(pos.x, pos.y))
+            let foot = (dir.normalize_or_zero() * spec.reach + vec2(pos.x, pos.y))
                 .extend(0.0)
                 .xzy();
-            let knee = solve_knee(body, foot, R1, R2);
+            let mut joints = vec![body; spec.segments.len() + 1];
+            solve_chain(
+                &mut joints,
+                &spec.segments,
+                &spec.joint_limits,
+                foot,
+                leg_pole(body, spec),
+            );
 
             CritterLeg {
                 local_body: body,
                 local_comfy_position: foot,
-                comfy_distance: 0.4,
-                global_knee: knee,
+                comfy_distance: spec.stride,
+                global_joints: joints,
                 global_body: body,
                 global_foot: foot,
                 global_previous_target: foot,
                 global_target: foot,
                 t: 0.0,
-                animation_speed: 6.,
+                animation_speed: spec.gait_speed,
             }
         })
         .collect_vec()
-        .try_into()
-        .unwrap();
+}
 
-    commands
-        .spawn((
-            MaterialMeshBundle {
-                mesh: meshes.add(shape::Box::new(0., 0., 0.).into()),
-                material,
-                transform: Transform::from_translation(vec3(0.0, BODY_CLEAR, 0.0)),
-                ..default()
-            },
-            Critter {
-                legs,
-                velocity: Vec3::ZERO,
-                moving: false,
-                set_priorety: false,
-                just_moved: false,
+/// Give everything with a [`MeshDetail`] high detail within range of any active camera, and
+/// low detail otherwise. The choice is made once per critter rather than per camera, since
+/// all cameras draw the same mesh.
+fn select_critter_detail(
+    cameras: Query<(&Camera, &GlobalTransform, Option<&CritterLod>)>,
+    mut critters: Query<(&GlobalTransform, &mut MeshDetail)>,
+) {
+    let default_lod = CritterLod::default();
+    for (transform, mut detail) in critters.iter_mut() {
+        let margin = match *detail {
+            MeshDetail::High => LOD_HYSTERESIS,
+            MeshDetail::Low => 0.0,
+        };
+        let near = cameras.iter().filter(|(camera, ..)| camera.is_active).any(
+            |(_, camera_transform, lod)| {
+                let range = lod.unwrap_or(&default_lod).high_detail_distance + margin;
+                camera_transform
+                    .translation()
+                    .distance_squared(transform.translation())
+                    < range * range
             },
-        ))
-        .id()
+        );
+        let wanted = if near {
+            MeshDetail::High
+        } else {
+            MeshDetail::Low
+        };
+        // Only write on a switch so change detection stays quiet
+        if *detail != wanted {
+            *detail = wanted;
+        }
+    }
 }
 
 fn update_critter_mesh(
-    mut critters: Query<(&mut Handle<Mesh>, &GlobalTransform, &Critter)>,
+    critters: Query<(&Handle<Mesh>, &GlobalTransform, &Critter, &MeshDetail)>,
     mut meshes: ResMut<Assets<Mesh>>,
 ) {
-    for (mesh, transform, cirtter) in critters.iter_mut() {
-        let new_mesh = make_hlod_critter_mesh(&cirtter.legs, *transform);
-        let mesh_ptr = mesh.into_inner();
-        *mesh_ptr = meshes.add(new_mesh);
+    for (mesh, transform, cirtter, detail) in critters.iter() {
+        if let Some(mesh) = meshes.get_mut(mesh) {
+            write_critter_mesh(
+                mesh,
+                cirtter.leg_joints(),
+                *transform,
+                &cirtter.spec,
+                *detail,
+            );
+        }
     }
 }
 // fn leg_transform_between(a: Vec3, b: Vec3) -> Transform {
@@ -157,193 +504,315 @@ R1| || \\   \
 Old            Target
 ------- time (t) ----------->
 */
-fn coordinate_critter(
+pub fn coordinate_critter(
     // time: Res<Time>,
+    data: Res<SceneData>,
+    mut rng: ResMut<CritterRng>,
     mut critters: Query<(&mut Critter, &GlobalTransform)>,
     // mut transforms: Query<&mut Transform, Without<Critter>>,
 ) {
     // let delta = time.delta_seconds();
-    let mut rng = thread_rng();
+    let rng = &mut rng.0;
     for (mut critter, critter_transform) in critters.iter_mut() {
         let vel = critter.velocity;
-        let (set1, set2): (Vec<_>, Vec<_>) = critter
+        let reach = critter.spec.leg_length();
+        let legs = critter
             .legs
-            .iter_mut()
-            .enumerate()
-            .map(|(i, leg)| {
+            .iter()
+            .map(|leg| {
                 let global_comfy_postion =
                     critter_transform.transform_point(leg.local_comfy_position);
-                let knee_angle = (leg.global_body - leg.global_knee)
+                let knee = leg.global_joints[leg.global_joints.len() - 2];
+                let knee_angle = (leg.global_body - knee)
                     .normalize_or_zero()
-                    .dot((leg.global_foot - leg.global_knee).normalize_or_zero());
+                    .dot((leg.global_foot - knee).normalize_or_zero());
                 let global_comfy_direction =
                     (global_comfy_postion.xz() - leg.global_body.xz()).normalize_or_zero();
                 let leg_angle = global_comfy_direction
                     .dot((leg.global_foot.xz() - leg.global_body.xz()).normalize_or_zero());
                 let wants_to_move = knee_angle > 0.85
                     || leg_angle < 0.7
-                    || leg.global_body.distance(leg.global_foot) >= R1 + R2;
-                // || leg.global_knee.distance(leg.global_body) < 0.01;
-                (
-                    global_comfy_direction,
-                    wants_to_move,
-                    i,
-                    global_comfy_postion,
-                )
+                    || leg.global_body.distance(leg.global_foot) >= reach;
+                // || knee.distance(leg.global_body) < 0.01;
+                (global_comfy_direction, wants_to_move, global_comfy_postion)
             })
-            .tuples()
-            .unzip();
+            .collect_vec();
         let move_leg = |global_comfy_direction,
                         global_comfy_postion,
                         leg: &mut CritterLeg,
-                        rng: &mut rand::rngs::ThreadRng| {
+                        rng: &mut ChaCha8Rng| {
             leg.global_previous_target = leg.global_foot;
-            leg.global_target = global_comfy_postion
+            let target = global_comfy_postion
                 + vel.clamp_length_max(
                     leg.comfy_distance
                         * (1.0 + 0.5 * vel.normalize().xz().dot(global_comfy_direction)),
                 ) * 0.4;
+            leg.global_target = plant_foot(&data, leg.global_body, target, reach);
             leg.t = (-rng.gen_range(-0.0..1.0) + vel.length()).min(0.0);
         };
 
         if critter.moving {
-            if set1.iter().all(|(_, _, i, _)| critter.legs[*i].t == 1.0)
-                && set2.iter().all(|(_, _, i, _)| critter.legs[*i].t == 1.0)
-            {
+            if critter.legs.iter().all(|leg| leg.t == 1.0) {
                 critter.moving = false;
-                critter.set_priorety = !critter.set_priorety;
-                critter.just_moved = true;
-            }
-        } else if set1.iter().any(|ele| ele.1) && (critter.set_priorety || !critter.just_moved) {
-            critter.moving = true;
-            for (global_comfy_direction, _wants_to_move, i, global_comfy_postion) in set1 {
-                let leg: &mut CritterLeg = &mut critter.legs[i];
-                move_leg(global_comfy_direction, global_comfy_postion, leg, &mut rng);
             }
-        } else if set2.iter().any(|ele| ele.1) && (!critter.set_priorety || !critter.just_moved) {
-            critter.moving = true;
-            for (global_comfy_direction, _wants_to_move, i, global_comfy_postion) in set2 {
+            continue;
+        }
+        // Only change gait between steps, so the new one starts from all feet planted
+        let gait = gait_for_speed(&critter.spec.gaits, vel.length());
+        if gait != critter.gait {
+            critter.gait = gait;
+            critter.next_group = 0;
+        }
+        if !legs.iter().any(|(_, wants_to_move, _)| *wants_to_move) {
+            continue;
+        }
+        let (gait, count) = (critter.gait.gait(), legs.len());
+        let group = critter.next_group % gait.groups(count);
+        for (i, (global_comfy_direction, _, global_comfy_postion)) in legs.into_iter().enumerate() {
+            if gait.group(i, count) == group {
                 let leg: &mut CritterLeg = &mut critter.legs[i];
-                move_leg(global_comfy_direction, global_comfy_postion, leg, &mut rng);
+                move_leg(global_comfy_direction, global_comfy_postion, leg, rng);
             }
-        } else {
-            critter.just_moved = false;
         }
+        debug_assert!(
+            (0..count)
+                .filter(|i| gait.group(*i, count) != group)
+                .count()
+                >= gait.min_grounded(count)
+        );
+        critter.next_group = (group + 1) % gait.groups(count);
+        critter.moving = true;
     }
 }
 
-fn update_critter(time: Res<Time>, mut critters: Query<(&mut Critter, &GlobalTransform)>) {
+pub fn update_critter(time: Res<Time>, mut critters: Query<(&mut Critter, &GlobalTransform)>) {
     let delta = time.delta_seconds();
     for (mut critter, critter_transform) in critters.iter_mut() {
         let vel = critter.velocity;
-        for mut leg in critter.legs.iter_mut() {
+        let Critter { spec, legs, .. } = &mut *critter;
+        for leg in legs.iter_mut() {
             let body = critter_transform.transform_point(leg.local_body);
-            let knee = solve_knee(body, leg.global_foot, R1, R2);
 
             leg.t = (leg.t + delta * (leg.animation_speed + 1.0 * vel.length())).min(1.0);
             let t = leg.t.max(0.0);
-            leg.global_foot = leg
-                .global_previous_target
-                .lerp(leg.global_target, t * t * (3.0 - 2.0 * t));
+            let (from, to) = (leg.global_previous_target, leg.global_target);
+            leg.global_foot = from.lerp(to, t * t * (3.0 - 2.0 * t));
+            // Reach the higher of the two heights early and leave it late so feet clear the
+            // edge of the block they step onto or off of
+            let rise = if to.y > from.y {
+                (t * 2.0).min(1.0)
+            } else {
+                (t * 2.0 - 1.0).max(0.0)
+            };
             leg.global_foot.y =
-                t.mul(t).mul(1.0 - t) * leg.global_previous_target.distance(leg.global_target);
-            leg.global_knee = knee;
+                from.y + (to.y - from.y) * rise + t.mul(t).mul(1.0 - t) * from.distance(to);
+            leg.global_joints[0] = body;
+            solve_chain(
+                &mut leg.global_joints,
+                &spec.segments,
+                &spec.joint_limits,
+                leg.global_foot,
+                leg_pole(body, spec),
+            );
             leg.global_body = body;
         }
     }
 }
 
-fn solve_knee(body: Vec3, foot: Vec3, r1: f32, r2: f32) -> Vec3 {
-    let foot = foot - body;
-    let foot_xz = foot.xz();
-    let foot_xz_len = foot_xz.length();
-    let foot_xz_nor = foot_xz / foot_xz_len;
-    let Vec2 {
-        x: knee_dist,
-        y: knee_height,
-    } = solve(vec2(foot_xz_len, foot.y), r1, r2);
-    vec3(
-        foot_xz_nor.x * knee_dist,
-        knee_height,
-        foot_xz_nor.y * knee_dist,
-    ) + body
-}
-
-// Adapted from https://iquilezles.org/articles/simpleik/
-fn solve(p: Vec2, r1: f32, r2: f32) -> Vec2 {
-    let h = p.dot(p);
-    let w = h + r1 * r1 - r2 * r2;
-    let s = (4.0 * r1 * r1 * h - w * w).max(0.0);
-    let result = (w * p + vec2(-p.y, p.x) * s.sqrt()) * 0.5 / h;
-    assert_ne!(result.x, f32::NAN);
-    assert_ne!(result.x, f32::NAN);
-    result
-}
-
-fn make_llod_critter_mesh(legs: &[CritterLeg], global_transform: GlobalTransform) -> Mesh {
-    let mut pos = Vec::with_capacity(10 * 8);
-    let mut nor = Vec::with_capacity(10 * 8);
-    let mut indices = Vec::with_capacity(45 * 8);
-    let leg_indices = leg_llod_indeices();
+/// Put a foot target on whatever is below it, pulling it back towards the body when that's a
+/// block too tall for the leg to reach.
+fn plant_foot(data: &SceneData, body: Vec3, target: Vec3, reach: f32) -> Vec3 {
+    const STEPS: usize = 8;
+    (0..=STEPS)
+        .map(|i| body.lerp(target, 1.0 - i as f32 / STEPS as f32))
+        .map(|p| vec3(p.x, data.ground_height(p.xz()), p.z))
+        .find(|p| p.y - body.y < reach * 0.9)
+        .unwrap_or(vec3(target.x, data.ground_height(target.xz()), target.z))
+}
 
-    let trans = global_transform.affine().inverse();
-    for leg in legs {
-        let (more_positions, more_normals) = make_llod_leg_mesh(
-            // global_transform.transform_point(leg.global_body),
-            // global_transform.transform_point(leg.global_knee),
-            // global_transform.transform_point(leg.global_foot),
-            trans.transform_point3(leg.global_body),
-            trans.transform_point3(leg.global_knee),
-            trans.transform_point3(leg.global_foot),
-            LEG_WIDTH * 0.5,
-        );
-        leg_indices
-            .iter()
-            .map(|i| i + pos.len() as u16)
-            .for_each(|i| indices.push(i));
-        pos.extend_from_slice(&more_positions);
-        nor.extend_from_slice(&more_normals);
+/// Raise the body to sit `body_clear` above the average planted foot and tilt it to match the
+/// slope between its front and back, and left and right, feet. On top of that the body lags
+/// behind changes in velocity on a spring, leaning into the sway.
+fn update_critter_body(
+    time: Res<Time>,
+    mut critters: Query<(&mut Critter, &mut Transform, &GlobalTransform)>,
+) {
+    let delta = time.delta_seconds();
+    if delta <= 0.0 {
+        return;
     }
+    let follow = (delta * BODY_FOLLOW).min(1.0);
+    for (mut critter, mut transform, global_transform) in critters.iter_mut() {
+        // Take last frame's sway back off so the feet are fitted against the resting pose
+        let parent = global_transform.affine() * transform.compute_affine().inverse();
+        transform.translation -= critter.sway;
+        transform.rotation = (transform.rotation * critter.lean.inverse()).normalize();
+        let to_local = (parent * transform.compute_affine()).inverse();
+
+        let feet = |planted_only: bool| {
+            critter
+                .legs
+                .iter()
+                .filter(|leg| leg.planted() || !planted_only)
+                .map(|leg| (leg.local_body, to_local.transform_point3(leg.global_foot)))
+                .collect_vec()
+        };
+        let fit = |feet: Vec<(Vec3, Vec3)>| {
+            let mean = |f: &dyn Fn(Vec3) -> bool| {
+                let (sum, count) = feet
+                    .iter()
+                    .filter(|(body, _)| f(*body))
+                    .fold((Vec3::ZERO, 0.0), |(sum, count), (_, foot)| {
+                        (sum + *foot, count + 1.0)
+                    });
+                (count > 0.0).then(|| sum / count)
+            };
+            Some((
+                mean(&|_| true)?,
+                mean(&|body| body.z < 0.0)?,
+                mean(&|body| body.z > 0.0)?,
+                mean(&|body| body.x < 0.0)?,
+                mean(&|body| body.x > 0.0)?,
+            ))
+        };
+        // Mid stride there may not be a planted foot on every side, fall back to all of them
+        if let Some((all, front, back, left, right)) = fit(feet(true)).or_else(|| fit(feet(false)))
+        {
+            // Feet are measured in the body's current frame, so these are corrections to it
+            let height = all.y + critter.spec.body_clear;
+            let pitch = (front.y - back.y).atan2(back.z - front.z);
+            let roll = (left.y - right.y).atan2(right.x - left.x);
+            let target_rotation = transform.rotation
+                * Quat::from_rotation_x(pitch * follow)
+                * Quat::from_rotation_z(-roll * follow);
+            transform.translation.y += height * follow;
+            transform.rotation = target_rotation.normalize();
+        }
+
+        let (_, parent_rotation, _) = parent.to_scale_rotation_translation();
+        let force = -SWAY_STIFFNESS * critter.sway
+            - SWAY_DAMPING * critter.sway_velocity
+            - SWAY_INERTIA * (parent_rotation.inverse() * critter.acceleration);
+        critter.sway_velocity += force * delta;
+        critter.sway = (critter.sway + critter.sway_velocity * delta).clamp_length_max(MAX_SWAY);
+        // Lagging behind pitches the nose up, swinging out to one side rolls towards it
+        let sway = transform.rotation.inverse() * critter.sway;
+        critter.lean = Quat::from_rotation_x(sway.z * LEAN) * Quat::from_rotation_z(-sway.x * LEAN);
+        transform.translation += critter.sway;
+        transform.rotation = (transform.rotation * critter.lean).normalize();
+    }
+}
+
+/// Point the joints of a leg bend towards, straight above where it attaches so knees point up.
+fn leg_pole(body: Vec3, spec: &CritterSpec) -> Vec3 {
+    body + Vec3::Y * spec.leg_length()
+}
+
+/// Mesh for a critter body with three sided legs through the given world space joints, in the
+/// space of `global_transform`.
+pub fn make_llod_critter_mesh<'a>(
+    legs: impl Iterator<Item = &'a [Vec3]>,
+    global_transform: GlobalTransform,
+    spec: &CritterSpec,
+) -> Mesh {
     let mut mesh = Mesh::new(bevy::render::render_resource::PrimitiveTopology::TriangleList);
-    mesh.set_indices(Some(bevy::render::mesh::Indices::U16(indices)));
-    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, pos);
-    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, nor);
+    write_critter_mesh(&mut mesh, legs, global_transform, spec, MeshDetail::Low);
     mesh
 }
 
-fn make_hlod_critter_mesh(legs: &[CritterLeg], global_transform: GlobalTransform) -> Mesh {
-    let mut pos = Vec::with_capacity(10 * 8);
-    let mut nor = Vec::with_capacity(10 * 8);
-    let mut indices = Vec::with_capacity(45 * 8);
-    let more_indices = leg_llod_indeices();
-    let trans = global_transform.affine().inverse();
-    for leg in legs {
-        let (more_positions, more_normals) = make_llod_leg_mesh(
-            // global_transform.transform_point(leg.global_body),
-            // global_transform.transform_point(leg.global_knee),
-            // global_transform.transform_point(leg.global_foot),
-            trans.transform_point3(leg.global_body),
-            trans.transform_point3(leg.global_knee),
-            trans.transform_point3(leg.global_foot),
-            LEG_WIDTH,
-            // 3,
-            // 100,
-            // 100,
-        );
-        more_indices
-            .iter()
-            .map(|i| i + pos.len() as u16)
-            .for_each(|i| indices.push(i));
-        pos.extend_from_slice(&more_positions);
-        nor.extend_from_slice(&more_normals);
-    }
+/// Mesh for a critter body with round, curved legs through the given world space joints, in
+/// the space of `global_transform`.
+pub fn make_hlod_critter_mesh<'a>(
+    legs: impl Iterator<Item = &'a [Vec3]>,
+    global_transform: GlobalTransform,
+    spec: &CritterSpec,
+) -> Mesh {
     let mut mesh = Mesh::new(bevy::render::render_resource::PrimitiveTopology::TriangleList);
-    mesh.set_indices(Some(bevy::render::mesh::Indices::U16(indices)));
-    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, pos);
-    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, nor);
+    write_critter_mesh(&mut mesh, legs, global_transform, spec, MeshDetail::High);
     mesh
 }
 
+/// Overwrite the vertices of a critter mesh, reusing its buffers. Indices are only rebuilt
+/// when the number of vertices changes, like when switching `detail`.
+pub fn write_critter_mesh<'a>(
+    mesh: &mut Mesh,
+    legs: impl Iterator<Item = &'a [Vec3]>,
+    global_transform: GlobalTransform,
+    spec: &CritterSpec,
+    detail: MeshDetail,
+) {
+    let mut buffers = MeshBuffers::take(mesh);
+    let segments = spec.segments.len();
+    let leg_vertices = match detail {
+        MeshDetail::Low => 6 * segments + 1,
+        MeshDetail::High => HLOD_SIDES as usize * (segments * HLOD_SPACERS as usize + 1) + 1,
+    };
+    let vertices = spec.legs * leg_vertices + body_vertex_count();
+    let indices = buffers.indices.is_empty() || buffers.positions.len() != vertices;
+    buffers.positions.clear();
+    buffers.normals.clear();
+    if indices {
+        buffers.indices.clear();
+    }
+    let trans = global_transform.affine().inverse();
+    for joints in legs {
+        match detail {
+            MeshDetail::Low => {
+                make_llod_leg_mesh(joints, trans, spec.leg_width, &mut buffers, indices)
+            }
+            MeshDetail::High => {
+                make_hlod_leg_mesh(joints, trans, spec.leg_width * 0.5, &mut buffers, indices)
+            }
+        }
+    }
+    make_body_mesh(spec, &mut buffers, indices);
+    buffers.put(mesh);
+}
+
+/// Vertex and index data of a critter mesh, taken out of the mesh and put back so updating
+/// it every frame doesn't allocate.
+#[derive(Default)]
+struct MeshBuffers {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    indices: Vec<u16>,
+}
+
+impl MeshBuffers {
+    fn take(mesh: &mut Mesh) -> Self {
+        let take = |values| match values {
+            Some(VertexAttributeValues::Float32x3(values)) => values,
+            _ => Vec::new(),
+        };
+        let indices = match mesh.indices_mut() {
+            Some(Indices::U16(indices)) => std::mem::take(indices),
+            _ => Vec::new(),
+        };
+        Self {
+            positions: take(mesh.remove_attribute(Mesh::ATTRIBUTE_POSITION)),
+            normals: take(mesh.remove_attribute(Mesh::ATTRIBUTE_NORMAL)),
+            indices,
+        }
+    }
+
+    fn put(self, mesh: &mut Mesh) {
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.set_indices(Some(Indices::U16(self.indices)));
+    }
+
+    fn vertex(&mut self, position: Vec3, normal: Vec3) {
+        self.positions.push(position.to_array());
+        self.normals.push(normal.to_array());
+    }
+
+    /// Add indices counted from `base`, the number of vertices before the ones they refer to.
+    fn indices(&mut self, base: usize, indices: impl IntoIterator<Item = u16>) {
+        let base = base as u16;
+        self.indices.extend(indices.into_iter().map(|i| i + base));
+    }
+}
+
 /*     5* --- 8*
        | \  k | \
      3|*  *4-6*-7
@@ -357,129 +826,197 @@ fn make_hlod_critter_mesh(legs: &[CritterLeg], global_transform: GlobalTransform
                      9*--*10
                        \|/
                         F12
+A ring of 3 at the body, two around every joint and one above the foot, then the tip.
 */
-fn leg_llod_indeices() -> Vec<u16> {
-    [
-        &quad(0, 2, 3, 5)[..],
-        &quad(1, 0, 4, 3)[..],
-        &quad(2, 1, 5, 4)[..],
-        &quad(3, 5, 6, 8)[..],
-        &quad(4, 3, 7, 6)[..],
-        &quad(5, 4, 8, 7)[..],
-        &quad(6, 8, 9, 11)[..],
-        &quad(7, 6, 10, 9)[..],
-        &quad(8, 7, 11, 10)[..],
-        &[11, 9, 12][..],
-        &[10, 11, 12][..],
-        &[9, 10, 12][..],
-    ]
-    .concat()
+fn leg_llod_indeices(joints: usize) -> Vec<u16> {
+    let rings = 2 * joints.saturating_sub(1) as u16;
+    let tip = rings * 3;
+    (0..rings.saturating_sub(1))
+        .map(|ring| ring * 3)
+        .flat_map(|b| {
+            [
+                quad(b, b + 2, b + 3, b + 5),
+                quad(b + 1, b, b + 4, b + 3),
+                quad(b + 2, b + 1, b + 5, b + 4),
+            ]
+        })
+        .flatten()
+        .chain([
+            tip - 1,
+            tip - 3,
+            tip,
+            tip - 2,
+            tip - 1,
+            tip,
+            tip - 3,
+            tip - 2,
+            tip,
+        ])
+        .collect()
 }
 
+/// Add a leg through the world space `joints` to `buffers`, moved into local space by `trans`.
 fn make_llod_leg_mesh(
-    body: Vec3,
-    knee: Vec3,
-    foot: Vec3,
+    joints: &[Vec3],
+    trans: Affine3A,
     thickness: f32,
-) -> (Vec<Vec3>, Vec<Vec3>) {
+    buffers: &mut MeshBuffers,
+    indices: bool,
+) {
+    let base = buffers.positions.len();
+    let joint = |i: usize| trans.transform_point3(joints[i]);
+    let (body, foot) = (joint(0), joint(joints.len() - 1));
+    let last_joint = joint(joints.len().saturating_sub(2));
     let leg_dir = (foot - body).xz().extend(0.0).xzy().normalize_or_zero();
-    let mut vertices = Vec::with_capacity(10);
-    let mut normals = Vec::with_capacity(10);
-    for (position, axis) in [
-        (body, leg_dir),
-        (knee - leg_dir * 0.075, leg_dir),
-        (knee - leg_dir * 0.025, (foot - body).normalize_or_zero()),
-        (
+    let rings = std::iter::once((body, leg_dir))
+        .chain((1..joints.len() - 1).map(joint).flat_map(|knee| {
+            [
+                (knee - leg_dir * 0.075, leg_dir),
+                (knee - leg_dir * 0.025, (foot - body).normalize_or_zero()),
+            ]
+        }))
+        .chain([(
             foot + vec3(-leg_dir.x, 0.5, -leg_dir.y) * 0.01,
-            (foot - knee).normalize_or_zero(),
-        ),
-    ] {
+            (foot - last_joint).normalize_or_zero(),
+        )]);
+    for (position, axis) in rings {
         for k in 0..3 {
             let theta = k as f32 / 3.0 * TAU;
             let para = axis.cross(Vec3::Y);
             let para = para.cross(axis) * thickness;
             let displace = erot(para, axis, theta);
-            normals.push(displace);
-            vertices.push(position + displace);
+            buffers.vertex(position + displace, displace);
         }
     }
-    vertices.push(foot);
-    normals.push(foot.normalize());
-    (vertices, normals)
+    buffers.vertex(foot, foot.normalize());
+    if indices {
+        buffers.indices(base, leg_llod_indeices(joints.len()));
+    }
 }
 
+fn body_vertex_count() -> usize {
+    ((BODY_STACKS + 1) * BODY_SECTORS) as usize
+}
+
+/// An ellipsoid around where the legs attach, wide enough to cover their roots.
+fn make_body_mesh(spec: &CritterSpec, buffers: &mut MeshBuffers, indices: bool) {
+    let base = buffers.positions.len();
+    let radii = vec3(
+        spec.attachment.x * 0.5 + spec.body_width * 0.5,
+        spec.body_width * 0.35,
+        spec.attachment.y + spec.body_width * 0.3,
+    );
+    let center = vec3(0.0, 0.02, 0.0);
+    for stack in 0..=BODY_STACKS {
+        let (sin_phi, cos_phi) = (stack as f32 / BODY_STACKS as f32 * PI).sin_cos();
+        for sector in 0..BODY_SECTORS {
+            let (sin_theta, cos_theta) = (sector as f32 / BODY_SECTORS as f32 * TAU).sin_cos();
+            let unit = vec3(sin_phi * cos_theta, cos_phi, sin_phi * sin_theta);
+            // Scaling a sphere by `radii` scales its normals by the inverse
+            buffers.vertex(center + unit * radii, (unit / radii).normalize());
+        }
+    }
+    if !indices {
+        return;
+    }
+    let indices = (0..BODY_STACKS)
+        .flat_map(|stack| (0..BODY_SECTORS).map(move |sector| (stack, sector)))
+        .flat_map(|(stack, sector)| {
+            let next = (sector + 1) % BODY_SECTORS;
+            let ring = stack * BODY_SECTORS;
+            quad(
+                ring + sector,
+                ring + BODY_SECTORS + sector,
+                ring + next,
+                ring + BODY_SECTORS + next,
+            )
+        });
+    buffers.indices(base, indices);
+}
+
+/*
+   B ring ring ring K ring ring ring
+   *----*----*----*--*--*                 Every segment gets HLOD_SPACERS rings of
+                        \                 HLOD_SIDES vertices, from the joint it starts
+                         *                at up to the next one. Segments ease in and out
+                          *               of each joint, the last one only eases in so the
+                          |               foot comes down onto the ground.
+                          * ring above F
+                          F tip
+*/
+/// Add a round leg through the world space `joints` to `buffers`, moved into local space by
+/// `trans`.
 fn make_hlod_leg_mesh(
-    body: Vec3,
-    knee: Vec3,
-    foot: Vec3,
-    thickness: f32,
-    segments: usize,
-    upper_spacers: usize,
-    lower_spacers: usize,
-) -> (Vec<Vec3>, Vec<Vec3>, Vec<u16>) {
-    let leg_dir = (foot - body).xz().extend(0.0).xzy();
-    let num_vetices = segments * (3 + upper_spacers + lower_spacers) + 1;
-    let mut positions = Vec::with_capacity(num_vetices);
-    let mut normals = Vec::with_capacity(num_vetices);
-    let mut indices = Vec::new();
-
-    let mut sector = |position, axis, positions: &mut Vec<Vec3>| {
-        for k in 0..segments {
-            let theta = k as f32 / 3.0 * TAU;
-            let displace = erot(vec3(0.0, thickness, 0.0), axis, theta);
-            normals.push(displace);
-            positions.push(position + displace);
+    joints: &[Vec3],
+    trans: Affine3A,
+    radius: f32,
+    buffers: &mut MeshBuffers,
+    indices: bool,
+) {
+    let base = buffers.positions.len();
+    let joint = |i: usize| trans.transform_point3(joints[i]);
+    let (body, foot) = (joint(0), joint(joints.len() - 1));
+    let leg_dir = (foot - body).xz().extend(0.0).xzy().normalize_or_zero();
+    // Each ring's frame is the last one turned onto the new axis so the legs don't twist
+    let mut up = Vec3::Y;
+    let mut ring = |center: Vec3, axis: Vec3| {
+        up = (up - axis * up.dot(axis))
+            .try_normalize()
+            .unwrap_or_else(|| leg_dir.cross(Vec3::Y).cross(axis).normalize_or_zero());
+        let side = axis.cross(up);
+        for k in 0..HLOD_SIDES {
+            let (sin, cos) = (k as f32 / HLOD_SIDES as f32 * TAU).sin_cos();
+            let normal = side * cos + up * sin;
+            buffers.vertex(center + normal * radius, normal);
         }
     };
 
-    let mut sector_indices = |start| {
-        // let start = start - 2;
-        for edge in (0..=segments).map(|i: usize| -> usize { i + start }) {
-            let prev = edge.checked_sub(1).unwrap_or(segments);
-            let next = &quad(
-                prev as u16,
-                edge as u16,
-                (prev - segments) as u16,
-                (edge - segments) as u16,
-            );
-            println!("{:?}", next);
-            indices.extend_from_slice(next);
+    let segments = joints.len() - 1;
+    for i in 0..segments {
+        let (a, b) = (joint(i), joint(i + 1));
+        let last = i + 1 == segments;
+        let straight = (b - a).normalize_or_zero();
+        for d in 0..HLOD_SPACERS {
+            let t = d as f32 / HLOD_SPACERS as f32;
+            let (h, slope) = match last {
+                false => (smoothstep(t), smoothstep_deriv(t)),
+                true => (t * t, 2.0 * t),
+            };
+            let center = a.xz().lerp(b.xz(), t).extend(a.y + (b.y - a.y) * h).xzy();
+            let tangent = (b - a).xz().extend((b.y - a.y) * slope).xzy();
+            ring(center, tangent.try_normalize().unwrap_or(straight));
         }
-    };
+    }
+    let last_joint = joint(segments - 1);
+    let end_axis = ((foot - last_joint)
+        .xz()
+        .extend((foot.y - last_joint.y) * 2.0)
+        .xzy())
+    .try_normalize()
+    .unwrap_or(Vec3::NEG_Y);
+    ring(foot - end_axis * radius * 2.0, end_axis);
+    buffers.vertex(foot, end_axis);
 
-    sector(body, leg_dir, &mut positions);
-    for d in 1..upper_spacers {
-        let t = d as f32 / upper_spacers as f32;
-        let h = smoothstep(t) * (knee.y - body.y) + body.y;
-        let position = body.xz().lerp(knee.xz(), t).extend(h).xzy();
-        // let theta = smoothstep_deriv(t).atan();
-        // let axis = erot(leg_dir, perpendicular, theta);
-        sector(position, leg_dir, &mut positions);
-        sector_indices(positions.len());
-    }
-    sector(knee, leg_dir, &mut positions);
-    sector_indices(positions.len());
-    for d in 1..lower_spacers {
-        let t = d as f32 / lower_spacers as f32;
-        let h = knee.y - 2.0 * (knee.y - foot.y) * smoothstep(t);
-        let position = knee.xz().lerp(foot.xz(), t).extend(h).xzy();
-        // let theta = smoothstep_deriv(t).atan();
-        // let axis = erot(leg_dir, perpendicular, theta);
-        sector(position, leg_dir, &mut positions);
-        sector_indices(positions.len());
-    }
-    sector(foot - leg_dir.normalize() * 0.02, leg_dir, &mut positions);
-    sector_indices(positions.len());
-    positions.push(foot);
-    normals.push(leg_dir);
-
-    let last = positions.len();
-    for edge in 0..segments {
-        let prev = segments.checked_sub(1).unwrap_or(segments);
-        indices.extend_from_slice(&[edge as u16, prev as u16, last as u16]);
-    }
-
-    (positions, normals, indices)
+    if indices {
+        buffers.indices(base, leg_hlod_indices(segments as u16 * HLOD_SPACERS + 1));
+    }
+}
+
+/// Indices for `rings` rings of [`HLOD_SIDES`] vertices followed by the tip.
+fn leg_hlod_indices(rings: u16) -> impl Iterator<Item = u16> {
+    let tip = rings * HLOD_SIDES;
+    let sides = (0..rings - 1).flat_map(|ring| {
+        (0..HLOD_SIDES).flat_map(move |k| {
+            let a = ring * HLOD_SIDES + k;
+            let b = ring * HLOD_SIDES + (k + 1) % HLOD_SIDES;
+            quad(a, b, a + HLOD_SIDES, b + HLOD_SIDES)
+        })
+    });
+    let cap = (0..HLOD_SIDES).flat_map(move |k| {
+        let last = tip - HLOD_SIDES;
+        [last + k, tip, last + (k + 1) % HLOD_SIDES]
+    });
+    sides.chain(cap)
 }
 
 fn smoothstep(x: f32) -> f32 {
@@ -501,3 +1038,156 @@ const fn quad<T: Copy>(a: T, b: T, c: T, d: T) -> [T; 6] {
     // b---d
     [a, c, b, b, c, d]
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::{sim::GameSimPlugin, state::GameState};
+    use bevy::render::mesh::MeshVertexAttribute;
+
+    fn attribute(mesh: &Mesh, attribute: MeshVertexAttribute) -> &[[f32; 3]] {
+        match mesh.attribute(attribute.id) {
+            Some(VertexAttributeValues::Float32x3(values)) => values,
+            _ => panic!("no {}", attribute.name),
+        }
+    }
+
+    /// Triangles should only index real vertices, each of which has a normal.
+    fn assert_well_formed(mesh: &Mesh) {
+        let positions = attribute(mesh, Mesh::ATTRIBUTE_POSITION);
+        let normals = attribute(mesh, Mesh::ATTRIBUTE_NORMAL);
+        assert_eq!(positions.len(), normals.len());
+        let indices = mesh.indices().unwrap();
+        assert_eq!(indices.len() % 3, 0);
+        assert!(indices.iter().all(|i| i < positions.len()));
+    }
+
+    #[test]
+    fn hlod_legs_face_outwards() {
+        let joints = [Vec3::ZERO, vec3(0.2, 0.15, 0.1), vec3(0.35, -0.2, 0.2)];
+        let mut buffers = MeshBuffers::default();
+        make_hlod_leg_mesh(&joints, Affine3A::IDENTITY, 0.01, &mut buffers, true);
+        let mut mesh = Mesh::new(bevy::render::render_resource::PrimitiveTopology::TriangleList);
+        buffers.put(&mut mesh);
+        assert_well_formed(&mesh);
+
+        let positions = attribute(&mesh, Mesh::ATTRIBUTE_POSITION);
+        let normals = attribute(&mesh, Mesh::ATTRIBUTE_NORMAL);
+        assert!(normals.iter().all(|&n| Vec3::from(n).is_normalized()));
+        for triangle in &mesh.indices().unwrap().iter().chunks(3) {
+            let triangle = triangle.collect_vec();
+            let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(positions[triangle[i]]));
+            let normal: Vec3 = triangle.iter().map(|&i| Vec3::from(normals[i])).sum();
+            assert!((b - a).cross(c - a).dot(normal) > 0.0, "{triangle:?}");
+        }
+    }
+
+    #[test]
+    fn switching_detail_rebuilds_indices() {
+        for spec in [
+            CritterSpec::scout(),
+            CritterSpec::soldier(),
+            CritterSpec::brute(),
+        ] {
+            // Standing, with the feet down where the body is held above them
+            let legs = rest_legs(&spec)
+                .into_iter()
+                .map(|mut leg| {
+                    let foot = leg.global_foot - Vec3::Y * spec.body_clear;
+                    let pole = leg_pole(leg.global_body, &spec);
+                    let (lengths, limits) = (&spec.segments, &spec.joint_limits);
+                    solve_chain(&mut leg.global_joints, lengths, limits, foot, pole);
+                    leg.global_joints
+                })
+                .collect_vec();
+            let legs = || legs.iter().map(|joints| &joints[..]);
+            let transform = GlobalTransform::default();
+
+            let mut mesh = make_llod_critter_mesh(legs(), transform, &spec);
+            assert_well_formed(&mesh);
+            for detail in [MeshDetail::High, MeshDetail::Low, MeshDetail::High] {
+                write_critter_mesh(&mut mesh, legs(), transform, &spec, detail);
+                assert_well_formed(&mesh);
+                let fresh = match detail {
+                    MeshDetail::Low => make_llod_critter_mesh(legs(), transform, &spec),
+                    MeshDetail::High => make_hlod_critter_mesh(legs(), transform, &spec),
+                };
+                assert_eq!(
+                    mesh.indices().unwrap().iter().collect_vec(),
+                    fresh.indices().unwrap().iter().collect_vec()
+                );
+            }
+        }
+    }
+
+    const CRITTERS: usize = 100;
+    const FRAMES: u32 = 200;
+
+    /// Walks 100 critters and reports the time per frame, run with
+    /// `cargo test --release critter_mesh_benchmark -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn critter_mesh_benchmark() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin::default())
+            .add_plugin(TransformPlugin)
+            .add_asset::<Mesh>()
+            .add_asset::<StandardMaterial>()
+            .insert_resource(SceneData::new(32, 32, 1.0, Vec2::splat(-16.0)))
+            .add_plugin(GameSimPlugin)
+            .insert_resource(NextState(Some(GameState::Playing)))
+            .add_plugin(CritterPlugin)
+            .add_startup_system(|mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>| {
+                let presets = [
+                    CritterSpec::scout(),
+                    CritterSpec::soldier(),
+                    CritterSpec::brute(),
+                ];
+                for i in 0..CRITTERS {
+                    let spec = presets[i % presets.len()].clone();
+                    make_cirtter(
+                        &mut commands,
+                        spec,
+                        Handle::<StandardMaterial>::default(),
+                        &mut meshes,
+                    );
+                }
+            })
+            .add_system(
+                |mut critters: Query<(&mut Critter, &mut Transform)>, time: Res<Time>| {
+                    for (i, (mut critter, mut transform)) in critters.iter_mut().enumerate() {
+                        let angle = time.elapsed_seconds() + i as f32;
+                        critter.velocity = vec3(angle.cos(), 0.0, angle.sin()) * 2.0;
+                        transform.translation += critter.velocity * time.delta_seconds();
+                    }
+                },
+            );
+        app.update();
+        let targets = |app: &mut App| {
+            let world = &mut app.world;
+            world
+                .query::<&Critter>()
+                .iter(world)
+                .map(|critter| critter.legs().iter().map(CritterLeg::target).collect_vec())
+                .collect_vec()
+        };
+        let before = targets(&mut app);
+
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            app.update();
+        }
+        let frame = start.elapsed() / FRAMES;
+        println!("{CRITTERS} critters: {frame:?} per frame");
+
+        let after = targets(&mut app);
+        let stepped = before.iter().zip(&after).filter(|(a, b)| a != b).count();
+        assert!(stepped > CRITTERS / 2, "only {stepped} critters stepped");
+
+        // Meshes are updated in place, never added
+        assert_eq!(app.world.resource::<Assets<Mesh>>().len(), CRITTERS);
+    }
+}
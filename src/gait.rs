@@ -0,0 +1,212 @@
+//! Leg coordination patterns. A gait splits a critter's legs into groups that take their steps
+//! together, one group after the other, so how many feet are planted at once is decided by
+//! the gait rather than by whichever legs happen to be stretched furthest.
+//!
+//! Legs are numbered the way [`crate::critter::make_cirtter`] lays them out: up the right side
+//! from the back, then down the left side from the front.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub trait Gait: Send + Sync {
+    /// How many groups one cycle of the gait steps through.
+    fn groups(&self, legs: usize) -> usize;
+    /// The group `leg` steps with, in `0..self.groups(legs)`.
+    fn group(&self, leg: usize, legs: usize) -> usize;
+    /// The fewest feet this gait keeps on the ground.
+    fn min_grounded(&self, legs: usize) -> usize;
+}
+
+/// Which side of the body a leg is on and how far it is from the back, counting pairs.
+fn side_and_position(leg: usize, legs: usize) -> (Side, usize) {
+    let half = legs / 2;
+    if leg < half {
+        (Side::Right, leg)
+    } else {
+        (Side::Left, legs - 1 - leg)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Right,
+    Left,
+}
+
+/// Alternating tripods: every other leg down each side, with opposite legs out of step.
+/// Fast, half the legs are always planted.
+pub struct Tripod;
+
+impl Gait for Tripod {
+    fn groups(&self, _legs: usize) -> usize {
+        2
+    }
+
+    fn group(&self, leg: usize, _legs: usize) -> usize {
+        leg % 2
+    }
+
+    fn min_grounded(&self, legs: usize) -> usize {
+        legs / 2
+    }
+}
+
+/// One leg at a time, back to front up the right side and then the left. Slow and stable.
+pub struct Wave;
+
+impl Gait for Wave {
+    fn groups(&self, legs: usize) -> usize {
+        legs
+    }
+
+    fn group(&self, leg: usize, legs: usize) -> usize {
+        match side_and_position(leg, legs) {
+            (Side::Right, position) => position,
+            (Side::Left, position) => legs / 2 + position,
+        }
+    }
+
+    fn min_grounded(&self, legs: usize) -> usize {
+        legs.saturating_sub(1)
+    }
+}
+
+/// A wave down each side with the left side half a cycle behind the right, so one leg per
+/// side is in the air at a time.
+pub struct Ripple;
+
+impl Gait for Ripple {
+    fn groups(&self, legs: usize) -> usize {
+        (legs / 2).max(1)
+    }
+
+    fn group(&self, leg: usize, legs: usize) -> usize {
+        let groups = self.groups(legs);
+        match side_and_position(leg, legs) {
+            (Side::Right, position) => position % groups,
+            (Side::Left, position) => (position + groups.div_ceil(2)) % groups,
+        }
+    }
+
+    fn min_grounded(&self, legs: usize) -> usize {
+        legs.saturating_sub(2)
+    }
+}
+
+/// Bounding, the back legs push off together and then the front legs land together.
+pub struct Gallop;
+
+impl Gait for Gallop {
+    fn groups(&self, _legs: usize) -> usize {
+        2
+    }
+
+    fn group(&self, leg: usize, legs: usize) -> usize {
+        let (_, position) = side_and_position(leg, legs);
+        usize::from(position * 2 >= legs / 2)
+    }
+
+    fn min_grounded(&self, legs: usize) -> usize {
+        let back = (0..legs).filter(|leg| self.group(*leg, legs) == 0).count();
+        back.min(legs - back)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect, FromReflect, Serialize, Deserialize)]
+pub enum GaitKind {
+    Tripod,
+    Wave,
+    Ripple,
+    Gallop,
+}
+
+impl GaitKind {
+    pub fn gait(self) -> &'static dyn Gait {
+        match self {
+            GaitKind::Tripod => &Tripod,
+            GaitKind::Wave => &Wave,
+            GaitKind::Ripple => &Ripple,
+            GaitKind::Gallop => &Gallop,
+        }
+    }
+}
+
+/// Use `gait` once the critter is moving at least `min_speed`.
+#[derive(Debug, Clone, Copy, PartialEq, Reflect, FromReflect, Serialize, Deserialize)]
+pub struct GaitSpeed {
+    pub min_speed: f32,
+    pub gait: GaitKind,
+}
+
+/// The gait for the fastest `min_speed` that `speed` has reached, the first one when it's
+/// slower than all of them.
+pub fn gait_for_speed(gaits: &[GaitSpeed], speed: f32) -> GaitKind {
+    gaits
+        .iter()
+        .filter(|gait| speed >= gait.min_speed)
+        .max_by(|a, b| a.min_speed.total_cmp(&b.min_speed))
+        .or(gaits.first())
+        .map_or(GaitKind::Tripod, |gait| gait.gait)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAITS: [GaitKind; 4] = [
+        GaitKind::Tripod,
+        GaitKind::Wave,
+        GaitKind::Ripple,
+        GaitKind::Gallop,
+    ];
+
+    #[test]
+    fn every_leg_steps_once_per_cycle() {
+        for kind in GAITS {
+            for legs in [4, 6, 8] {
+                let gait = kind.gait();
+                for leg in 0..legs {
+                    assert!(gait.group(leg, legs) < gait.groups(legs), "{kind:?} {legs}");
+                }
+                for group in 0..gait.groups(legs) {
+                    assert!(
+                        (0..legs).any(|leg| gait.group(leg, legs) == group),
+                        "{kind:?} with {legs} legs never steps group {group}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn walking_gaits_are_statically_stable() {
+        for kind in [GaitKind::Tripod, GaitKind::Wave, GaitKind::Ripple] {
+            for legs in [6, 8] {
+                assert!(kind.gait().min_grounded(legs) >= 3, "{kind:?} {legs}");
+            }
+            assert!(kind.gait().min_grounded(4) >= 2, "{kind:?} 4");
+        }
+    }
+
+    #[test]
+    fn picks_gait_by_speed() {
+        let gaits = [
+            GaitSpeed {
+                min_speed: 0.0,
+                gait: GaitKind::Wave,
+            },
+            GaitSpeed {
+                min_speed: 0.5,
+                gait: GaitKind::Ripple,
+            },
+            GaitSpeed {
+                min_speed: 1.5,
+                gait: GaitKind::Tripod,
+            },
+        ];
+        assert_eq!(gait_for_speed(&gaits, 0.1), GaitKind::Wave);
+        assert_eq!(gait_for_speed(&gaits, 1.0), GaitKind::Ripple);
+        assert_eq!(gait_for_speed(&gaits, 3.0), GaitKind::Tripod);
+        assert_eq!(gait_for_speed(&[], 3.0), GaitKind::Tripod);
+    }
+}
@@ -0,0 +1,524 @@
+//! Gait tuning tool. `--gait-trace <preset>` walks a single critter along a scripted velocity
+//! path on flat ground at a fixed time step, recording its legs every step. The trace is
+//! written out as CSV and JSON for plotting foot trajectories, then played back in the arena
+//! where it can be paused and scrubbed. The same commit always records the same trace, so
+//! two exports can be diffed to see what a gait change did.
+
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use bevy::{prelude::*, time::TimeUpdateStrategy, utils::Instant};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    critter::{
+        make_cirtter, make_hlod_critter_mesh, write_critter_mesh, Critter, CritterPlugin,
+        CritterRng, CritterSpec, MeshDetail,
+    },
+    gait::GaitKind,
+    scene::SceneData,
+    sim::GameSimPlugin,
+    state::GameState,
+};
+
+/// Time between recorded frames.
+const TRACE_STEP: f32 = 1.0 / 60.0;
+/// Seed for the stagger critters add to their steps, fixed so traces are reproducible.
+const TRACE_SEED: u64 = 0;
+/// How many seconds of trace holding an arrow key scrubs through per second.
+const SCRUB_SPEED: f32 = 0.5;
+
+/// Records and plays back a trace when given settings, does nothing otherwise.
+pub struct GaitTracePlugin(pub Option<GaitTraceSettings>);
+impl Plugin for GaitTracePlugin {
+    fn build(&self, app: &mut App) {
+        let Some(settings) = self.0.clone() else {
+            return;
+        };
+        app.insert_resource(settings)
+            .init_resource::<TracePlayback>()
+            .add_startup_system(record_trace)
+            .add_system(play_trace.run_if(resource_exists::<GaitTrace>()));
+    }
+}
+
+/// Read from `--gait-trace <scout|soldier|brute|default>`, with `--gait-script <file.ron>` to
+/// walk a [`VelocityScript`] other than the default one and `--trace-out <path>` to choose
+/// where `<path>.csv` and `<path>.json` are written.
+#[derive(Debug, Clone, Resource)]
+pub struct GaitTraceSettings {
+    pub spec: CritterSpec,
+    pub script: Option<PathBuf>,
+    pub out: PathBuf,
+}
+
+impl GaitTraceSettings {
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Option<Self>> {
+        let mut spec = None;
+        let mut script = None;
+        let mut out = None;
+        let mut args = args.skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--gait-trace" => {
+                    spec = Some(match args.next().as_deref() {
+                        Some("scout") => CritterSpec::scout(),
+                        Some("soldier") => CritterSpec::soldier(),
+                        Some("brute") => CritterSpec::brute(),
+                        Some("default") => CritterSpec::default(),
+                        other => bail!(
+                            "unknown critter {other:?}, expected scout, soldier, brute or default"
+                        ),
+                    })
+                }
+                "--gait-script" => match args.next() {
+                    Some(path) => script = Some(PathBuf::from(path)),
+                    None => bail!("--gait-script needs a path to a velocity script"),
+                },
+                "--trace-out" => match args.next() {
+                    Some(path) => out = Some(PathBuf::from(path)),
+                    None => bail!("--trace-out needs a path to write the trace to"),
+                },
+                _ => {}
+            }
+        }
+        let Some(spec) = spec else {
+            if script.is_some() || out.is_some() {
+                bail!("--gait-script and --trace-out need a --gait-trace preset to record");
+            }
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            spec,
+            script,
+            out: out.unwrap_or_else(|| PathBuf::from("gait_trace")),
+        }))
+    }
+}
+
+/// Velocities to walk at over time, interpolated between keys in order of time. Written in
+/// RON as `(keys: [(time: 0.0, velocity: (0.0, 0.0, -1.0)), ...])`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VelocityScript {
+    pub keys: Vec<VelocityKey>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct VelocityKey {
+    pub time: f32,
+    pub velocity: Vec3,
+}
+
+impl VelocityScript {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        ron::from_str(&text).with_context(|| format!("parsing {}", path.display()))
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keys.last().map_or(0.0, |key| key.time)
+    }
+
+    pub fn velocity(&self, time: f32) -> Vec3 {
+        match self.keys.iter().position(|key| key.time > time) {
+            None => self.keys.last().map_or(Vec3::ZERO, |key| key.velocity),
+            Some(0) => self.keys[0].velocity,
+            Some(i) => {
+                let (a, b) = (self.keys[i - 1], self.keys[i]);
+                let t = (time - a.time) / (b.time - a.time);
+                a.velocity.lerp(b.velocity, t)
+            }
+        }
+    }
+}
+
+impl Default for VelocityScript {
+    /// Stand, walk forwards, run, strafe, walk back and stop, with a second between each.
+    fn default() -> Self {
+        let keys = [
+            (0.0, Vec3::ZERO),
+            (1.0, Vec3::ZERO),
+            (2.0, Vec3::NEG_Z),
+            (5.0, Vec3::NEG_Z),
+            (6.0, Vec3::NEG_Z * 3.0),
+            (9.0, Vec3::NEG_Z * 3.0),
+            (10.0, Vec3::X * 2.0),
+            (12.0, Vec3::X * 2.0),
+            (13.0, Vec3::Z),
+            (15.0, Vec3::Z),
+            (16.0, Vec3::ZERO),
+            (18.0, Vec3::ZERO),
+        ];
+        Self {
+            keys: keys
+                .into_iter()
+                .map(|(time, velocity)| VelocityKey { time, velocity })
+                .collect(),
+        }
+    }
+}
+
+/// Every recorded step of one critter walking a [`VelocityScript`].
+#[derive(Debug, Clone, Serialize, Deserialize, Resource)]
+pub struct GaitTrace {
+    pub spec: CritterSpec,
+    /// Seconds between frames.
+    pub step: f32,
+    pub frames: Vec<TraceFrame>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceFrame {
+    pub time: f32,
+    pub velocity: Vec3,
+    /// Where the body is, with its sway and lean.
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub gait: GaitKind,
+    pub legs: Vec<LegFrame>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LegFrame {
+    /// How far through its step the leg is, see [`crate::critter::CritterLeg::step`].
+    pub step: f32,
+    pub target: Vec3,
+    pub foot: Vec3,
+    /// From the body out to the foot, knees in between.
+    pub joints: Vec<Vec3>,
+}
+
+impl TraceFrame {
+    fn new(time: f32, critter: &Critter, transform: &GlobalTransform) -> Self {
+        let (_, rotation, position) = transform.to_scale_rotation_translation();
+        Self {
+            time,
+            velocity: critter.velocity,
+            position,
+            rotation,
+            gait: critter.gait(),
+            legs: critter
+                .legs()
+                .iter()
+                .map(|leg| LegFrame {
+                    step: leg.step(),
+                    target: leg.target(),
+                    foot: leg.foot(),
+                    joints: leg.joints().to_vec(),
+                })
+                .collect(),
+        }
+    }
+
+    fn transform(&self) -> Transform {
+        Transform::from_translation(self.position).with_rotation(self.rotation)
+    }
+}
+
+/// Marks the entity moved along the script, the recorded critter is its child just like a
+/// critter riding a physics body.
+#[derive(Component)]
+struct Walker;
+
+/// Walk a critter shaped like `spec` along `script` in a headless app, one [`TRACE_STEP`] at a
+/// time.
+pub fn record(spec: CritterSpec, script: &VelocityScript) -> GaitTrace {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(TransformPlugin)
+        .add_plugin(AssetPlugin::default())
+        .add_asset::<Mesh>()
+        .add_asset::<StandardMaterial>()
+        .insert_resource(SceneData::centered(64, 64, 1.0))
+        .insert_resource(CritterRng(ChaCha8Rng::seed_from_u64(TRACE_SEED)))
+        .add_plugin(GameSimPlugin)
+        .add_plugin(CritterPlugin)
+        .insert_resource(NextState(Some(GameState::Playing)));
+    let walker_spec = spec.clone();
+    app.add_startup_system(
+        move |mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>| {
+            let material = Handle::<StandardMaterial>::default();
+            let critter = make_cirtter(&mut commands, walker_spec.clone(), material, &mut meshes);
+            commands
+                .spawn((Walker, TransformBundle::default()))
+                .add_child(critter);
+        },
+    );
+
+    // Time steps exactly rather than however long each update really took
+    let start = Instant::now();
+    let steps = (script.duration() / TRACE_STEP).ceil() as usize;
+    let mut frames = Vec::with_capacity(steps + 1);
+    for i in 0..=steps {
+        let time = i as f32 * TRACE_STEP;
+        let velocity = script.velocity(time);
+        let world = &mut app.world;
+        let instant = start + Duration::from_secs_f32(time);
+        world.insert_resource(TimeUpdateStrategy::ManualInstant(instant));
+        for mut transform in world
+            .query_filtered::<&mut Transform, With<Walker>>()
+            .iter_mut(world)
+        {
+            transform.translation += velocity * TRACE_STEP;
+        }
+        for mut critter in world.query::<&mut Critter>().iter_mut(world) {
+            critter.velocity = velocity;
+        }
+        app.update();
+        let world = &mut app.world;
+        let (critter, transform) = world.query::<(&Critter, &GlobalTransform)>().single(world);
+        frames.push(TraceFrame::new(time, critter, transform));
+    }
+    GaitTrace {
+        spec,
+        step: TRACE_STEP,
+        frames,
+    }
+}
+
+impl GaitTrace {
+    pub fn duration(&self) -> f32 {
+        self.frames.last().map_or(0.0, |frame| frame.time)
+    }
+
+    pub fn frame_at(&self, time: f32) -> Option<&TraceFrame> {
+        let i = (time / self.step).round().max(0.0) as usize;
+        self.frames.get(i.min(self.frames.len().saturating_sub(1)))
+    }
+
+    /// One row per leg per frame, with the knees as `joint1`, `joint2`...
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "time,leg,gait,speed,step,target_x,target_y,target_z,foot_x,foot_y,foot_z",
+        );
+        for joint in 1..self.spec.segments.len() {
+            let _ = write!(csv, ",joint{joint}_x,joint{joint}_y,joint{joint}_z");
+        }
+        csv.push('\n');
+        for frame in &self.frames {
+            for (i, leg) in frame.legs.iter().enumerate() {
+                let speed = frame.velocity.length();
+                let _ = write!(
+                    csv,
+                    "{},{i},{:?},{speed},{}",
+                    frame.time, frame.gait, leg.step
+                );
+                let knees = leg.joints[1..leg.joints.len() - 1].iter();
+                for point in [leg.target, leg.foot].iter().chain(knees) {
+                    let _ = write!(csv, ",{},{},{}", point.x, point.y, point.z);
+                }
+                csv.push('\n');
+            }
+        }
+        csv
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Write `out` with `.csv` and `.json` extensions.
+    pub fn export(&self, out: &Path) -> Result<()> {
+        std::fs::write(out.with_extension("csv"), self.to_csv())?;
+        std::fs::write(out.with_extension("json"), self.to_json()?)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Resource)]
+struct TracePlayback {
+    time: f32,
+    playing: bool,
+}
+
+impl Default for TracePlayback {
+    fn default() -> Self {
+        Self {
+            time: 0.0,
+            playing: true,
+        }
+    }
+}
+
+/// The critter a recorded trace is played back on.
+#[derive(Component)]
+struct TraceGhost;
+
+fn record_trace(
+    mut commands: Commands,
+    settings: Res<GaitTraceSettings>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let script = match &settings.script {
+        Some(path) => VelocityScript::load(path).unwrap_or_else(|err| {
+            error!("failed to load gait script, walking the default one: {err:#}");
+            VelocityScript::default()
+        }),
+        None => VelocityScript::default(),
+    };
+    let trace = record(settings.spec.clone(), &script);
+    match trace.export(&settings.out) {
+        Ok(()) => info!(
+            "recorded {} frames of gait to {}.csv and .json",
+            trace.frames.len(),
+            settings.out.display()
+        ),
+        Err(err) => error!("failed to export gait trace: {err}"),
+    }
+    info!("gait trace playback: P plays and pauses, hold left or right to scrub, home restarts");
+
+    let Some(frame) = trace.frames.first() else {
+        return;
+    };
+    let transform = frame.transform();
+    let legs = frame.legs.iter().map(|leg| &leg.joints[..]);
+    commands.spawn((
+        TraceGhost,
+        PbrBundle {
+            mesh: meshes.add(make_hlod_critter_mesh(legs, transform.into(), &trace.spec)),
+            material: materials.add(Color::rgb(1.0, 0.6, 0.2).into()),
+            transform,
+            ..default()
+        },
+    ));
+    commands.insert_resource(trace);
+}
+
+fn play_trace(
+    keys: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    trace: Res<GaitTrace>,
+    mut playback: ResMut<TracePlayback>,
+    mut ghosts: Query<(&mut Transform, &Handle<Mesh>), With<TraceGhost>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let delta = time.delta_seconds();
+    if keys.just_pressed(KeyCode::P) {
+        playback.playing = !playback.playing;
+    }
+    if keys.just_pressed(KeyCode::Home) {
+        playback.time = 0.0;
+    }
+    let scrub = keys.pressed(KeyCode::Right) as i32 - keys.pressed(KeyCode::Left) as i32;
+    if scrub != 0 {
+        playback.playing = false;
+        playback.time += scrub as f32 * SCRUB_SPEED * delta;
+    } else if playback.playing {
+        playback.time += delta;
+        // Loop back to the start
+        if playback.time > trace.duration() {
+            playback.time = 0.0;
+        }
+    }
+    playback.time = playback.time.clamp(0.0, trace.duration());
+
+    let Some(frame) = trace.frame_at(playback.time) else {
+        return;
+    };
+    for (mut transform, mesh) in ghosts.iter_mut() {
+        *transform = frame.transform();
+        if let Some(mesh) = meshes.get_mut(mesh) {
+            let legs = frame.legs.iter().map(|leg| &leg.joints[..]);
+            write_critter_mesh(
+                mesh,
+                legs,
+                (*transform).into(),
+                &trace.spec,
+                MeshDetail::High,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        ["shooter"]
+            .iter()
+            .chain(args)
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn settings_are_read_from_the_command_line() {
+        assert!(GaitTraceSettings::from_args(args(&[])).unwrap().is_none());
+        let settings = GaitTraceSettings::from_args(args(&[
+            "--trace-out",
+            "out/scout",
+            "--gait-trace",
+            "scout",
+        ]))
+        .unwrap()
+        .unwrap();
+        assert_eq!(settings.spec.legs, CritterSpec::scout().legs);
+        assert_eq!(settings.script, None);
+        assert_eq!(settings.out, PathBuf::from("out/scout"));
+
+        let settings = GaitTraceSettings::from_args(args(&["--gait-trace", "soldier"]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(settings.spec.legs, CritterSpec::soldier().legs);
+        assert_eq!(settings.out, PathBuf::from("gait_trace"));
+    }
+
+    #[test]
+    fn bad_settings_are_rejected() {
+        for bad in [
+            &["--gait-trace", "spider"][..],
+            &["--gait-trace"],
+            &["--gait-trace", "scout", "--gait-script"],
+            &["--gait-trace", "scout", "--trace-out"],
+            &["--trace-out", "out/scout"],
+        ] {
+            assert!(GaitTraceSettings::from_args(args(bad)).is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn scripts_interpolate_between_keys() {
+        let script = VelocityScript::default();
+        assert_eq!(script.velocity(-1.0), Vec3::ZERO);
+        assert_eq!(script.velocity(1.5), Vec3::NEG_Z * 0.5);
+        assert_eq!(script.velocity(3.0), Vec3::NEG_Z);
+        assert_eq!(script.velocity(100.0), Vec3::ZERO);
+    }
+
+    #[test]
+    fn recording_is_reproducible() {
+        let script = VelocityScript {
+            keys: vec![
+                VelocityKey {
+                    time: 0.0,
+                    velocity: Vec3::ZERO,
+                },
+                VelocityKey {
+                    time: 2.0,
+                    velocity: Vec3::NEG_Z * 2.0,
+                },
+            ],
+        };
+        let trace = record(CritterSpec::soldier(), &script);
+        assert_eq!(trace.frames.len(), 121);
+        assert_eq!(trace.frames, record(CritterSpec::soldier(), &script).frames);
+
+        // Header and one row per leg per frame
+        let rows = trace.to_csv().lines().count();
+        assert_eq!(rows, 1 + trace.frames.len() * trace.spec.legs);
+        // The critter walked off and its feet followed
+        let last = trace.frames.last().unwrap();
+        assert!(last.position.z < -1.0);
+        assert!(last.legs.iter().all(|leg| leg.foot.z < -0.5));
+    }
+}
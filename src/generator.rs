@@ -0,0 +1,422 @@
+//! Seeded procedural arenas. Every generator is a pure function of its seed and the size of
+//! the map it fills, so a seed from a bug report reproduces the exact same block layout.
+
+use anyhow::{bail, Result};
+use bevy::{math::vec2, prelude::*};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::scene::SceneData;
+
+pub trait MapGenerator: Send + Sync {
+    /// Overwrite every height in `data`, keeping its size, cell size and origin.
+    fn generate(&self, seed: u64, data: &mut SceneData);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GeneratorKind {
+    Noise,
+    Rooms,
+    Caves,
+}
+
+/// Which generator to use and how to seed it, read from `--generator <kind> --seed <n>`
+/// with an optional `--mirror` to make both team halves identical.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Resource)]
+pub struct GeneratorSettings {
+    pub kind: GeneratorKind,
+    pub seed: u64,
+    pub mirror: bool,
+}
+
+impl GeneratorSettings {
+    /// `None` when neither a generator nor a seed was asked for. Unknown generators and seeds
+    /// that aren't numbers are errors rather than being replaced, so a command line from a bug
+    /// report either reproduces its arena or says why it can't.
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Option<Self>> {
+        let mut kind = None;
+        let mut seed = None;
+        let mut mirror = false;
+        let mut args = args.skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--generator" => {
+                    kind = Some(match args.next().as_deref() {
+                        Some("noise") => GeneratorKind::Noise,
+                        Some("rooms") => GeneratorKind::Rooms,
+                        Some("caves") => GeneratorKind::Caves,
+                        other => {
+                            bail!("unknown generator {other:?}, expected noise, rooms or caves")
+                        }
+                    })
+                }
+                "--seed" => {
+                    let value = args.next();
+                    match value.as_deref().map(str::parse) {
+                        Some(Ok(value)) => seed = Some(value),
+                        _ => bail!("invalid seed {value:?}, expected a whole number"),
+                    }
+                }
+                "--mirror" => mirror = true,
+                _ => {}
+            }
+        }
+        if kind.is_none() && seed.is_none() {
+            if mirror {
+                bail!("--mirror needs a --generator or --seed to mirror");
+            }
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            kind: kind.unwrap_or(GeneratorKind::Noise),
+            seed: seed.unwrap_or_else(|| rand::thread_rng().gen()),
+            mirror,
+        }))
+    }
+
+    pub fn generator(&self) -> Box<dyn MapGenerator> {
+        let generator: Box<dyn MapGenerator> = match self.kind {
+            GeneratorKind::Noise => Box::<ValueNoise>::default(),
+            GeneratorKind::Rooms => Box::<Rooms>::default(),
+            GeneratorKind::Caves => Box::<Caves>::default(),
+        };
+        if self.mirror {
+            Box::new(Mirrored(generator))
+        } else {
+            generator
+        }
+    }
+
+    pub fn generate(&self, data: &mut SceneData) {
+        self.generator().generate(self.seed, data);
+    }
+}
+
+/// Fractal value noise, cells where the noise is above `threshold` become blocks that get
+/// taller the further above it they are.
+pub struct ValueNoise {
+    /// Size of a noise lattice cell, in map cells.
+    pub scale: f32,
+    /// Layers of noise summed together, at least one is always used.
+    pub octaves: u32,
+    pub threshold: f32,
+    pub min_height: f32,
+    pub max_height: f32,
+}
+
+impl Default for ValueNoise {
+    fn default() -> Self {
+        Self {
+            scale: 5.0,
+            octaves: 3,
+            threshold: 0.6,
+            min_height: 0.8,
+            max_height: 1.6,
+        }
+    }
+}
+
+impl MapGenerator for ValueNoise {
+    fn generate(&self, seed: u64, data: &mut SceneData) {
+        let octaves = self.octaves.max(1);
+        for (x, z) in data.cells().collect::<Vec<_>>() {
+            let mut value = 0.0;
+            let mut amplitude = 0.5;
+            let mut frequency = 1.0 / self.scale;
+            for octave in 0..octaves {
+                let p = vec2(x as f32, z as f32) * frequency;
+                value += amplitude * value_noise(seed.wrapping_add(octave as u64), p);
+                amplitude *= 0.5;
+                frequency *= 2.0;
+            }
+            let value = value / (1.0 - 0.5f32.powi(octaves as i32));
+            let height = if value > self.threshold {
+                let t = (value - self.threshold) / (1.0 - self.threshold);
+                self.min_height + (self.max_height - self.min_height) * t
+            } else {
+                0.0
+            };
+            data.set(x, z, height);
+        }
+    }
+}
+
+/// Rectangular rooms carved out of solid walls, joined in order by L shaped corridors.
+pub struct Rooms {
+    pub rooms: usize,
+    pub min_size: usize,
+    pub max_size: usize,
+    pub corridor_width: usize,
+    pub wall_height: f32,
+}
+
+impl Default for Rooms {
+    fn default() -> Self {
+        Self {
+            rooms: 8,
+            min_size: 3,
+            max_size: 7,
+            corridor_width: 2,
+            wall_height: 1.2,
+        }
+    }
+}
+
+impl MapGenerator for Rooms {
+    fn generate(&self, seed: u64, data: &mut SceneData) {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let (width, depth) = (data.width(), data.depth());
+        if width == 0 || depth == 0 {
+            return;
+        }
+        for (x, z) in data.cells().collect::<Vec<_>>() {
+            data.set(x, z, self.wall_height);
+        }
+        let mut carve = |x0: usize, z0: usize, x1: usize, z1: usize| {
+            for z in z0.min(z1)..=z0.max(z1).min(depth - 1) {
+                for x in x0.min(x1)..=x0.max(x1).min(width - 1) {
+                    data.set(x, z, 0.0);
+                }
+            }
+        };
+        // A `max_size` below `min_size` means rooms of exactly `min_size`
+        let sizes = self.min_size..=self.max_size.max(self.min_size);
+        let mut previous: Option<(usize, usize)> = None;
+        for _ in 0..self.rooms {
+            let w = rng.gen_range(sizes.clone()).clamp(1, width);
+            let d = rng.gen_range(sizes.clone()).clamp(1, depth);
+            let x = rng.gen_range(0..=width - w);
+            let z = rng.gen_range(0..=depth - d);
+            carve(x, z, x + w - 1, z + d - 1);
+            let center = (x + w / 2, z + d / 2);
+            if let Some((px, pz)) = previous {
+                let c = self.corridor_width.saturating_sub(1);
+                if rng.gen_bool(0.5) {
+                    carve(px, pz, center.0, pz + c);
+                    carve(center.0, pz, center.0 + c, center.1);
+                } else {
+                    carve(px, pz, px + c, center.1);
+                    carve(px, center.1, center.0, center.1 + c);
+                }
+            }
+            previous = Some(center);
+        }
+    }
+}
+
+/// Cellular automata caves: random fill smoothed by the 4-5 rule.
+pub struct Caves {
+    pub fill: f64,
+    pub steps: usize,
+    pub min_height: f32,
+    pub max_height: f32,
+}
+
+impl Default for Caves {
+    fn default() -> Self {
+        Self {
+            fill: 0.42,
+            steps: 4,
+            min_height: 0.8,
+            max_height: 1.4,
+        }
+    }
+}
+
+impl MapGenerator for Caves {
+    fn generate(&self, seed: u64, data: &mut SceneData) {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let (width, depth) = (data.width() as isize, data.depth() as isize);
+        let mut walls = (0..width * depth)
+            .map(|_| rng.gen_bool(self.fill))
+            .collect::<Vec<_>>();
+        for _ in 0..self.steps {
+            walls = (0..depth)
+                .flat_map(|z| (0..width).map(move |x| (x, z)))
+                .map(|(x, z)| {
+                    // Out of bounds counts as wall so caves close off at the edges
+                    let neighbours = (-1..=1)
+                        .flat_map(|dz| (-1..=1).map(move |dx| (x + dx, z + dz)))
+                        .filter(|(nx, nz)| (*nx, *nz) != (x, z))
+                        .filter(|(nx, nz)| {
+                            *nx < 0
+                                || *nz < 0
+                                || *nx >= width
+                                || *nz >= depth
+                                || walls[(*nz * width + *nx) as usize]
+                        })
+                        .count();
+                    match neighbours {
+                        n if n > 4 => true,
+                        n if n < 4 => false,
+                        _ => walls[(z * width + x) as usize],
+                    }
+                })
+                .collect();
+        }
+        for (x, z) in data.cells().collect::<Vec<_>>() {
+            let height = if walls[z * width as usize + x] {
+                self.min_height
+                    + (self.max_height - self.min_height) * hash(seed, x as i64, z as i64)
+            } else {
+                0.0
+            };
+            data.set(x, z, height);
+        }
+    }
+}
+
+/// Runs another generator then copies the -x half onto the +x half, so both teams get the
+/// same layout.
+pub struct Mirrored(pub Box<dyn MapGenerator>);
+
+impl MapGenerator for Mirrored {
+    fn generate(&self, seed: u64, data: &mut SceneData) {
+        self.0.generate(seed, data);
+        let width = data.width();
+        for z in 0..data.depth() {
+            for x in 0..width / 2 {
+                data.set(width - 1 - x, z, data.get(x, z));
+            }
+        }
+    }
+}
+
+/// A stable hash of a lattice point to `0.0..1.0`, the splitmix64 finalizer.
+fn hash(seed: u64, x: i64, z: i64) -> f32 {
+    let mut h = seed
+        ^ (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (z as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+    h = (h ^ (h >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    h ^= h >> 31;
+    (h >> 40) as f32 / (1u64 << 24) as f32
+}
+
+fn value_noise(seed: u64, p: Vec2) -> f32 {
+    let i = p.floor();
+    let f = p - i;
+    let u = f * f * (3.0 - 2.0 * f);
+    let (x, z) = (i.x as i64, i.y as i64);
+    let a = hash(seed, x, z);
+    let b = hash(seed, x + 1, z);
+    let c = hash(seed, x, z + 1);
+    let d = hash(seed, x + 1, z + 1);
+    a + (b - a) * u.x + (c - a) * u.y + (a - b - c + d) * u.x * u.y
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heights(generator: &dyn MapGenerator, seed: u64) -> Vec<f32> {
+        let mut data = SceneData::centered(24, 20, 1.0);
+        generator.generate(seed, &mut data);
+        data.cells().map(|(x, z)| data.get(x, z)).collect()
+    }
+
+    fn generators() -> Vec<(&'static str, Box<dyn MapGenerator>)> {
+        vec![
+            ("noise", Box::<ValueNoise>::default()),
+            ("rooms", Box::<Rooms>::default()),
+            ("caves", Box::<Caves>::default()),
+            ("mirrored", Box::new(Mirrored(Box::<Rooms>::default()))),
+        ]
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_heights() {
+        for (name, generator) in generators() {
+            assert_eq!(heights(&*generator, 7), heights(&*generator, 7), "{name}");
+        }
+    }
+
+    #[test]
+    fn different_seeds_give_different_heights() {
+        for (name, generator) in generators() {
+            assert_ne!(heights(&*generator, 7), heights(&*generator, 8), "{name}");
+        }
+    }
+
+    #[test]
+    fn mirrored_halves_match() {
+        for kind in [
+            GeneratorKind::Noise,
+            GeneratorKind::Rooms,
+            GeneratorKind::Caves,
+        ] {
+            let settings = GeneratorSettings {
+                kind,
+                seed: 3,
+                mirror: true,
+            };
+            let mut data = SceneData::centered(25, 20, 1.0);
+            settings.generate(&mut data);
+            let width = data.width();
+            for (x, z) in data.cells() {
+                assert_eq!(
+                    data.get(x, z),
+                    data.get(width - 1 - x, z),
+                    "{kind:?} {x} {z}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn zero_octaves_still_gives_finite_heights() {
+        let noise = ValueNoise {
+            octaves: 0,
+            ..default()
+        };
+        assert!(heights(&noise, 1).iter().all(|height| height.is_finite()));
+    }
+
+    #[test]
+    fn rooms_with_sizes_the_wrong_way_round_still_generate() {
+        let rooms = Rooms {
+            min_size: 5,
+            max_size: 2,
+            ..default()
+        };
+        // Every room is carved at `min_size`
+        assert!(heights(&rooms, 1).contains(&0.0));
+    }
+
+    #[test]
+    fn empty_maps_are_left_alone() {
+        for (_, generator) in generators() {
+            for (width, depth) in [(0, 0), (0, 5), (5, 0)] {
+                generator.generate(1, &mut SceneData::centered(width, depth, 1.0));
+            }
+        }
+    }
+
+    #[test]
+    fn bad_arguments_are_errors() {
+        let args = |args: &[&str]| {
+            GeneratorSettings::from_args(
+                ["shooter"]
+                    .iter()
+                    .chain(args)
+                    .map(|arg| arg.to_string())
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            )
+        };
+        assert_eq!(args(&[]).unwrap(), None);
+        assert_eq!(
+            args(&["--generator", "caves", "--seed", "12", "--mirror"]).unwrap(),
+            Some(GeneratorSettings {
+                kind: GeneratorKind::Caves,
+                seed: 12,
+                mirror: true,
+            })
+        );
+        assert!(args(&["--generator", "maze"]).is_err());
+        assert!(args(&["--generator"]).is_err());
+        assert!(args(&["--seed", "twelve"]).is_err());
+        assert!(args(&["--mirror"]).is_err());
+    }
+}
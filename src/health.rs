@@ -0,0 +1,356 @@
+//! Health and damage. Weapons and explosions are turned into [`DamageEvent`]s against whatever
+//! entity owns the [`Health`] that was hit, scaled by [`FriendlyFire`] when both sides are on
+//! the same [`Team`]; bodies that run out either respawn on their team's half or are removed
+//! from the world.
+
+use bevy::prelude::*;
+use rand::thread_rng;
+
+use crate::{
+    collision::Collider,
+    map::{Arena, MapFile},
+    projectile::{center, ExplosionEvent},
+    scene::SceneData,
+    sim::Physics,
+    team::{FriendlyFire, Team},
+    weapon::{HitEvent, HitTarget},
+};
+
+/// Seconds a respawned body can't be hurt for.
+const RESPAWN_INVULNERABILITY: f32 = 2.0;
+/// Share of an explosion's damage dealt to whoever fired it, rocket jumps shouldn't be fatal.
+const SELF_DAMAGE: f32 = 0.5;
+
+pub struct HealthPlugin;
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FriendlyFire>()
+            .register_type::<FriendlyFire>()
+            .add_event::<DamageEvent>()
+            .add_event::<DeathEvent>()
+            .add_systems(
+                (
+                    hit_damage,
+                    explosion_damage,
+                    apply_damage,
+                    respawn,
+                    remove_dead,
+                )
+                    .chain()
+                    .in_set(DamageSet),
+            );
+    }
+}
+
+/// Turning hits into damage and deaths, anything reacting to [`DeathEvent`]s the same frame
+/// runs after this.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DamageSet;
+
+#[derive(Debug, Component, Reflect)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+    /// Seconds left during which damage is ignored.
+    pub invulnerable: f32,
+}
+
+impl Health {
+    pub fn new(max: f32) -> Self {
+        Self {
+            current: max,
+            max,
+            invulnerable: 0.0,
+        }
+    }
+}
+
+/// Damage dealt by whatever carries it, e.g. a projectile.
+#[derive(Debug, Clone, Copy, Component, Reflect)]
+pub struct Damage(pub f32);
+
+/// Bodies with this come back at one of their team's spawn points instead of being removed
+/// when they die.
+#[derive(Debug, Clone, Copy, Component)]
+pub struct Respawns;
+
+#[derive(Debug, Clone, Copy)]
+pub struct DamageEvent {
+    /// Entity holding the [`Health`] that takes the damage.
+    pub target: Entity,
+    pub source: Entity,
+    pub amount: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DeathEvent {
+    pub entity: Entity,
+    pub killer: Entity,
+}
+
+/// The nearest of `entity` and its ancestors that has health, critter legs are children of
+/// the body that actually gets hurt.
+fn health_owner(
+    entity: Entity,
+    health: &Query<(), With<Health>>,
+    parents: &Query<&Parent>,
+) -> Option<Entity> {
+    let mut current = entity;
+    loop {
+        if health.contains(current) {
+            return Some(current);
+        }
+        current = parents.get(current).ok()?.get();
+    }
+}
+
+fn hit_damage(
+    mut hits: EventReader<HitEvent>,
+    friendly_fire: Res<FriendlyFire>,
+    health: Query<(), With<Health>>,
+    parents: Query<&Parent>,
+    teams: Query<&Team>,
+    mut damage: EventWriter<DamageEvent>,
+) {
+    for hit in hits.iter() {
+        let HitTarget::Critter(entity) = hit.target else {
+            continue;
+        };
+        let Some(target) = health_owner(entity, &health, &parents) else {
+            continue;
+        };
+        let scale = friendly_fire.scale(
+            teams.get(hit.shooter).ok().copied(),
+            teams.get(target).ok().copied(),
+        );
+        if scale > 0.0 {
+            damage.send(DamageEvent {
+                target,
+                source: hit.shooter,
+                amount: hit.damage * scale,
+            });
+        }
+    }
+}
+
+fn explosion_damage(
+    mut explosions: EventReader<ExplosionEvent>,
+    friendly_fire: Res<FriendlyFire>,
+    bodies: Query<(Entity, &Physics, &Collider, Option<&Team>), With<Health>>,
+    teams: Query<&Team>,
+    mut damage: EventWriter<DamageEvent>,
+) {
+    for explosion in explosions.iter() {
+        let owner_team = teams.get(explosion.owner).ok().copied();
+        for (entity, physics, collider, team) in bodies.iter() {
+            let falloff = explosion.falloff(center(physics, collider));
+            // Rocket jumps still cost their own health whatever the friendly fire rule
+            let scale = if entity == explosion.owner {
+                SELF_DAMAGE
+            } else {
+                friendly_fire.scale(owner_team, team.copied())
+            };
+            if falloff <= 0.0 || scale <= 0.0 {
+                continue;
+            }
+            damage.send(DamageEvent {
+                target: entity,
+                source: explosion.owner,
+                amount: explosion.damage * falloff * scale,
+            });
+        }
+    }
+}
+
+fn apply_damage(
+    time: Res<Time>,
+    mut events: EventReader<DamageEvent>,
+    mut bodies: Query<&mut Health>,
+    mut deaths: EventWriter<DeathEvent>,
+) {
+    for mut health in bodies.iter_mut() {
+        health.invulnerable = (health.invulnerable - time.delta_seconds()).max(0.0);
+    }
+    for event in events.iter() {
+        let Ok(mut health) = bodies.get_mut(event.target) else {
+            continue;
+        };
+        // Already dead bodies can still be caught in a blast the same frame
+        if health.invulnerable > 0.0 || health.current <= 0.0 {
+            continue;
+        }
+        health.current -= event.amount;
+        if health.current <= 0.0 {
+            deaths.send(DeathEvent {
+                entity: event.target,
+                killer: event.source,
+            });
+        }
+    }
+}
+
+fn respawn(
+    mut deaths: EventReader<DeathEvent>,
+    arena: Res<Arena>,
+    maps: Res<Assets<MapFile>>,
+    data: Res<SceneData>,
+    mut bodies: Query<(&mut Health, &mut Physics, &Team), With<Respawns>>,
+) {
+    let mut rng = thread_rng();
+    for death in deaths.iter() {
+        let Ok((mut health, mut physics, team)) = bodies.get_mut(death.entity) else {
+            continue;
+        };
+        let spawn = maps
+            .get(&arena.map)
+            .and_then(|map| map.spawn_point(*team, &data, &mut rng))
+            .unwrap_or(physics.position);
+        physics.teleport(spawn);
+        physics.velocity = Vec3::ZERO;
+        health.current = health.max;
+        health.invulnerable = RESPAWN_INVULNERABILITY;
+    }
+}
+
+fn remove_dead(
+    mut commands: Commands,
+    mut deaths: EventReader<DeathEvent>,
+    respawns: Query<(), With<Respawns>>,
+) {
+    for death in deaths.iter() {
+        debug!("{:?} was killed by {:?}", death.entity, death.killer);
+        if !respawns.contains(death.entity) {
+            commands.entity(death.entity).despawn_recursive();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::vec3;
+
+    use super::*;
+
+    /// A map split into a blue half at -x and a red half at +x with no spawn points, so
+    /// respawns land in the blue spawn zone at `-3 < x < -2`.
+    fn app() -> App {
+        let map = MapFile::parse(
+            br#"(
+                cell_size: 1.0,
+                block_color: (1.0, 1.0, 1.0),
+                halves: [
+                    (team: Blue, color: (0.4, 0.4, 1.0), min: (-3.0, -1.0), max: (0.0, 1.0)),
+                    (team: Red, color: (1.0, 0.4, 0.4), min: (0.0, -1.0), max: (3.0, 1.0)),
+                ],
+                spawns: [],
+                heights: [". . . . . .", ". . . . . ."],
+            )"#,
+        )
+        .unwrap();
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin::default())
+            .add_asset::<MapFile>()
+            .add_event::<HitEvent>()
+            .add_event::<ExplosionEvent>()
+            .insert_resource(map.scene_data().unwrap())
+            .add_plugin(HealthPlugin);
+        let map = app.world.resource_mut::<Assets<MapFile>>().add(map);
+        app.insert_resource(Arena {
+            map,
+            box_texture: default(),
+            block_material: default(),
+            floor_materials: Vec::new(),
+            floor_mesh: default(),
+            block_mesh: default(),
+            generator: None,
+        });
+        app
+    }
+
+    fn body(app: &mut App, health: Health, team: Team) -> Entity {
+        app.world
+            .spawn((
+                health,
+                team,
+                Physics::at(vec3(2.5, 0.0, 0.5)),
+                Collider::default(),
+            ))
+            .id()
+    }
+
+    fn damage(app: &mut App, target: Entity, amount: f32) {
+        app.world.send_event(DamageEvent {
+            target,
+            source: target,
+            amount,
+        });
+        app.update();
+    }
+
+    #[test]
+    fn damage_is_ignored_while_invulnerable() {
+        let mut app = app();
+        let target = body(
+            &mut app,
+            Health {
+                invulnerable: 10.0,
+                ..Health::new(10.0)
+            },
+            Team::Red,
+        );
+        damage(&mut app, target, 5.0);
+        assert_eq!(app.world.get::<Health>(target).unwrap().current, 10.0);
+
+        app.world.get_mut::<Health>(target).unwrap().invulnerable = 0.0;
+        damage(&mut app, target, 5.0);
+        assert_eq!(app.world.get::<Health>(target).unwrap().current, 5.0);
+    }
+
+    #[test]
+    fn dead_players_respawn_on_their_half() {
+        let mut app = app();
+        let target = body(&mut app, Health::new(10.0), Team::Blue);
+        app.world.entity_mut(target).insert(Respawns);
+        damage(&mut app, target, 25.0);
+
+        let health = app.world.get::<Health>(target).unwrap();
+        assert_eq!(health.current, health.max);
+        assert_eq!(health.invulnerable, RESPAWN_INVULNERABILITY);
+        let position = app.world.get::<Physics>(target).unwrap().position;
+        assert!(position.x > -3.0 && position.x < -2.0, "{position}");
+    }
+
+    #[test]
+    fn dead_critters_are_removed() {
+        let mut app = app();
+        let target = body(&mut app, Health::new(10.0), Team::Red);
+        damage(&mut app, target, 25.0);
+        assert!(app.world.get_entity(target).is_none());
+    }
+
+    #[test]
+    fn explosions_spare_teammates_but_not_their_owner() {
+        let mut app = app();
+        let owner = body(&mut app, Health::new(100.0), Team::Blue);
+        let teammate = body(&mut app, Health::new(100.0), Team::Blue);
+        let opponent = body(&mut app, Health::new(100.0), Team::Red);
+        let position = app
+            .world
+            .get::<Physics>(owner)
+            .map(|physics| center(physics, app.world.get::<Collider>(owner).unwrap()));
+        app.world.send_event(ExplosionEvent {
+            owner,
+            position: position.unwrap(),
+            radius: 100.0,
+            damage: 10.0,
+            knockback: 0.0,
+        });
+        app.update();
+
+        let current = |entity| app.world.get::<Health>(entity).unwrap().current;
+        assert!((current(owner) - (100.0 - 10.0 * SELF_DAMAGE)).abs() < 1e-4);
+        assert_eq!(current(teammate), 100.0);
+        assert!((current(opponent) - 90.0).abs() < 1e-4);
+    }
+}
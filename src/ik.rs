@@ -0,0 +1,217 @@
+//! Inverse kinematics for chains of any number of segments, solved with FABRIK
+//! (http://andreasaristidou.com/FABRIK.html). Every joint's bend is kept inside its
+//! [`JointLimit`] and the chain bends towards a pole point, so the knees of a leg all point
+//! the same way. Targets out of reach stretch the chain straight at them.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Iterations to spend getting the end of the chain onto the target.
+const ITERATIONS: usize = 10;
+/// Close enough to the target to stop iterating.
+const TOLERANCE: f32 = 1e-3;
+
+/// How far a joint can bend, as the angle between the segments either side of it. Zero is
+/// straight.
+#[derive(Debug, Clone, Copy, PartialEq, Reflect, FromReflect, Serialize, Deserialize)]
+pub struct JointLimit {
+    pub min_bend: f32,
+    pub max_bend: f32,
+}
+
+impl Default for JointLimit {
+    fn default() -> Self {
+        Self {
+            min_bend: 0.0,
+            max_bend: std::f32::consts::PI,
+        }
+    }
+}
+
+/// Move `joints` so the last one reaches `target`, keeping the first one where it is.
+///
+/// `lengths[i]` is the length of the segment from `joints[i]` to `joints[i + 1]` and
+/// `limits[i]` applies to `joints[i + 1]`, missing limits don't constrain their joint.
+pub fn solve_chain(
+    joints: &mut [Vec3],
+    lengths: &[f32],
+    limits: &[JointLimit],
+    target: Vec3,
+    pole: Vec3,
+) {
+    let segments = lengths.len().min(joints.len().saturating_sub(1));
+    if segments == 0 {
+        return;
+    }
+    let joints = &mut joints[..=segments];
+    let root = joints[0];
+    let total: f32 = lengths[..segments].iter().sum();
+    let fallback = direction(pole - root, Vec3::Y);
+
+    if root.distance(target) >= total {
+        let forward = direction(target - root, fallback);
+        for i in 0..segments {
+            joints[i + 1] = joints[i] + forward * lengths[i];
+        }
+        return;
+    }
+
+    bend_towards_pole(joints, pole);
+    for _ in 0..ITERATIONS {
+        // Backwards from the target
+        joints[segments] = target;
+        for i in (0..segments).rev() {
+            let back = direction(joints[i] - joints[i + 1], -fallback);
+            joints[i] = joints[i + 1] + back * lengths[i];
+        }
+        // Then forwards from the root, bending each joint no further than it is allowed to
+        joints[0] = root;
+        for i in 0..segments {
+            let mut forward = direction(joints[i + 1] - joints[i], fallback);
+            if let Some(limit) = i.checked_sub(1).and_then(|previous| limits.get(previous)) {
+                let incoming = direction(joints[i] - joints[i - 1], fallback);
+                forward = limit_bend(incoming, forward, *limit, pole - joints[i]);
+            }
+            joints[i + 1] = joints[i] + forward * lengths[i];
+        }
+        bend_towards_pole(joints, pole);
+        if joints[segments].distance(target) < TOLERANCE {
+            break;
+        }
+    }
+}
+
+/// Swing every inner joint around the line between its neighbours to face `pole`, which
+/// doesn't change any segment lengths.
+fn bend_towards_pole(joints: &mut [Vec3], pole: Vec3) {
+    for i in 1..joints.len().saturating_sub(1) {
+        let (a, b) = (joints[i - 1], joints[i + 1]);
+        let Some(axis) = (b - a).try_normalize() else {
+            continue;
+        };
+        let flatten = |v: Vec3| v - axis * v.dot(axis);
+        let (Some(from), Some(to)) = (
+            flatten(joints[i] - a).try_normalize(),
+            flatten(pole - a).try_normalize(),
+        ) else {
+            continue;
+        };
+        joints[i] = a + Quat::from_rotation_arc(from, to) * (joints[i] - a);
+    }
+}
+
+/// `outgoing` rotated towards or away from `incoming` until the bend between them is within
+/// `limit`, bending towards `pole` when they are parallel.
+fn limit_bend(incoming: Vec3, outgoing: Vec3, limit: JointLimit, pole: Vec3) -> Vec3 {
+    let bend = incoming.angle_between(outgoing);
+    let clamped = bend.clamp(limit.min_bend, limit.max_bend.max(limit.min_bend));
+    if (clamped - bend).abs() < f32::EPSILON {
+        return outgoing;
+    }
+    let axis = incoming
+        .cross(outgoing)
+        .try_normalize()
+        .or_else(|| incoming.cross(pole).try_normalize())
+        .unwrap_or_else(|| incoming.any_orthonormal_vector());
+    Quat::from_axis_angle(axis, clamped) * incoming
+}
+
+fn direction(v: Vec3, fallback: Vec3) -> Vec3 {
+    v.try_normalize().unwrap_or(fallback)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::vec3;
+
+    use super::*;
+
+    fn chain(lengths: &[f32]) -> Vec<Vec3> {
+        let mut position = Vec3::ZERO;
+        let mut joints = vec![position];
+        for length in lengths {
+            position.x += length;
+            joints.push(position);
+        }
+        joints
+    }
+
+    fn assert_lengths(joints: &[Vec3], lengths: &[f32]) {
+        for (pair, length) in joints.windows(2).zip(lengths) {
+            assert!((pair[0].distance(pair[1]) - length).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn reaches_targets_in_range() {
+        let lengths = [0.2, 0.2, 0.3];
+        let mut joints = chain(&lengths);
+        let target = vec3(0.3, -0.3, 0.1);
+        solve_chain(&mut joints, &lengths, &[], target, Vec3::Y);
+        assert!(joints[3].distance(target) < 1e-2);
+        assert_eq!(joints[0], Vec3::ZERO);
+        assert_lengths(&joints, &lengths);
+    }
+
+    #[test]
+    fn stretches_towards_unreachable_targets() {
+        let lengths = [0.2, 0.35];
+        let mut joints = chain(&lengths);
+        solve_chain(&mut joints, &lengths, &[], vec3(0.0, 0.0, 5.0), Vec3::Y);
+        assert!(joints[2].distance(vec3(0.0, 0.0, 0.55)) < 1e-4);
+        assert_lengths(&joints, &lengths);
+    }
+
+    #[test]
+    fn knees_bend_towards_the_pole() {
+        let lengths = [0.2, 0.35];
+        let mut joints = chain(&lengths);
+        solve_chain(
+            &mut joints,
+            &lengths,
+            &[],
+            vec3(0.3, -0.1, 0.0),
+            vec3(0.0, 1.0, 0.0),
+        );
+        assert!(joints[1].y > 0.0);
+        solve_chain(
+            &mut joints,
+            &lengths,
+            &[],
+            vec3(0.3, -0.1, 0.0),
+            vec3(0.0, -1.0, 0.0),
+        );
+        assert!(joints[1].y < -0.1);
+    }
+
+    #[test]
+    fn respects_joint_limits() {
+        let lengths = [0.3, 0.3];
+        let limit = JointLimit {
+            min_bend: 0.0,
+            max_bend: 1.0,
+        };
+        let mut joints = chain(&lengths);
+        // Reaching this close would need a much sharper bend than allowed
+        solve_chain(
+            &mut joints,
+            &lengths,
+            &[limit],
+            vec3(0.05, 0.0, 0.0),
+            Vec3::Y,
+        );
+        let bend = (joints[1] - joints[0]).angle_between(joints[2] - joints[1]);
+        assert!(bend <= 1.0 + 1e-3);
+        assert_lengths(&joints, &lengths);
+    }
+
+    #[test]
+    fn degenerate_input_stays_finite() {
+        let lengths = [0.2, 0.35];
+        let mut joints = vec![Vec3::ZERO; 3];
+        solve_chain(&mut joints, &lengths, &[], Vec3::ZERO, Vec3::ZERO);
+        assert!(joints.iter().all(|joint| joint.is_finite()));
+        solve_chain(&mut joints, &[], &[], Vec3::ONE, Vec3::Y);
+        solve_chain(&mut [], &lengths, &[], Vec3::ONE, Vec3::Y);
+    }
+}
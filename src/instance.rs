@@ -1,13 +1,17 @@
+//! Instanced rendering for meshes drawn many times over, like the arena blocks. Instances
+//! are lit just like [`MainMaterial`] and drawn in the opaque phase. The per-instance data
+//! is only copied to the render world and uploaded to the GPU on frames it changes, the
+//! buffer is kept between frames otherwise.
+
 use bevy::{
-    core_pipeline::core_3d::Transparent3d,
-    ecs::{
-        query::QueryItem,
-        system::{lifetimeless::*, SystemParamItem},
+    core_pipeline::core_3d::Opaque3d,
+    ecs::system::{lifetimeless::*, SystemParamItem},
+    pbr::{
+        MeshPipeline, MeshPipelineKey, MeshUniform, RenderMaterials, SetMeshBindGroup,
+        SetMeshViewBindGroup,
     },
-    pbr::{MeshPipeline, MeshPipelineKey, MeshUniform, SetMeshBindGroup, SetMeshViewBindGroup},
     prelude::*,
     render::{
-        extract_component::{ExtractComponent, ExtractComponentPlugin},
         mesh::{GpuBufferInfo, MeshVertexBufferLayout},
         render_asset::RenderAssets,
         render_phase::{
@@ -15,102 +19,76 @@ use bevy::{
             RenderPhase, SetItemPipeline, TrackedRenderPass,
         },
         render_resource::*,
-        renderer::RenderDevice,
-        view::{ExtractedView, NoFrustumCulling},
-        RenderApp, RenderSet,
+        renderer::{RenderDevice, RenderQueue},
+        view::ExtractedView,
+        Extract, ExtractSchedule, RenderApp, RenderSet,
     },
+    utils::HashMap,
 };
 use bytemuck::{Pod, Zeroable};
 
-// pub fn main() {
-//     App::new()
-//         .add_plugins(DefaultPlugins.set(AssetPlugin {
-//             watch_for_changes: true,
-//             ..Default::default()
-//         }))
-//         .add_plugin(CustomMaterialPlugin)
-//         .add_startup_system(setup)
-//         .run();
-// }
-
-pub fn setup(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
-    commands.spawn((
-        meshes.add(Mesh::from(shape::Cube { size: 0.5 })),
-        SpatialBundle::INHERITED_IDENTITY,
-        InstanceMaterialData(
-            (1..=1)
-                .flat_map(|x| (1..=10).map(move |y| (x as f32 / 10.0, y as f32 / 10.0)))
-                .map(|(x, y)| InstanceData {
-                    position: Vec3::new(x * 10.0 - 5.0, y * 10.0 - 5.0, 0.0),
-                    scale: 1.0,
-                    color: Color::hsla(x * 360., y, 0.5, 1.0).as_rgba_f32(),
-                })
-                .collect(),
-        ),
-        // NOTE: Frustum culling is done based on the Aabb of the Mesh and the GlobalTransform.
-        // As the cube is at the origin, if its Aabb moves outside the view frustum, all the
-        // instanced cubes will be culled.
-        // The InstanceMaterialData contains the 'GlobalTransform' information for this custom
-        // instancing, and that is not taken into account with the built-in frustum culling.
-        // We must disable the built-in frustum culling by adding the `NoFrustumCulling` marker
-        // component to avoid incorrect culling.
-        NoFrustumCulling,
-    ));
-
-    // camera
-}
+use crate::main_material::MainMaterial;
 
-#[derive(Component, Deref)]
-struct InstanceMaterialData(Vec<InstanceData>);
+/// Draws the entity's mesh once per instance instead of once. Frustum culling only sees the
+/// entity's own transform, so add `NoFrustumCulling` too unless every instance sits close
+/// to it.
+#[derive(Component, Deref, DerefMut)]
+pub struct InstanceMaterialData(pub Vec<InstanceData>);
 
-impl ExtractComponent for InstanceMaterialData {
-    type Query = &'static InstanceMaterialData;
-    type Filter = ();
-    type Out = Self;
+/// The material every instance is drawn with, tinted by each instance's color. Not a plain
+/// `Handle<MainMaterial>` so the material plugin doesn't also draw the mesh once by itself.
+#[derive(Component, Clone)]
+pub struct InstanceMaterial(pub Handle<MainMaterial>);
 
-    fn extract_component(item: QueryItem<'_, Self::Query>) -> Option<Self> {
-        Some(InstanceMaterialData(item.0.clone()))
-    }
-}
+/// Marks render world entities drawn with [`DrawCustom`], their instances are in
+/// [`InstanceBuffers`].
+#[derive(Component)]
+struct Instanced;
+
+/// Instances extracted on a frame they changed, waiting to be uploaded.
+#[derive(Component)]
+struct ChangedInstances(Vec<InstanceData>);
 
 pub struct CustomMaterialPlugin;
 
 impl Plugin for CustomMaterialPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugin(ExtractComponentPlugin::<InstanceMaterialData>::default());
         app.sub_app_mut(RenderApp)
-            .add_render_command::<Transparent3d, DrawCustom>()
+            .add_render_command::<Opaque3d, DrawCustom>()
             .init_resource::<CustomPipeline>()
             .init_resource::<SpecializedMeshPipelines<CustomPipeline>>()
+            .init_resource::<InstanceBuffers>()
+            .add_system(extract_instances.in_schedule(ExtractSchedule))
             .add_system(queue_custom.in_set(RenderSet::Queue))
             .add_system(prepare_instance_buffers.in_set(RenderSet::Prepare));
     }
 }
 
-#[derive(Clone, Copy, Pod, Zeroable)]
+#[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable)]
 #[repr(C)]
-struct InstanceData {
-    position: Vec3,
-    scale: f32,
-    color: [f32; 4],
+pub struct InstanceData {
+    pub position: Vec3,
+    /// Scales the mesh along y only, so a unit tall box becomes a block this tall.
+    pub height: f32,
+    pub color: [f32; 4],
 }
 
 #[allow(clippy::too_many_arguments)]
 fn queue_custom(
-    transparent_3d_draw_functions: Res<DrawFunctions<Transparent3d>>,
+    opaque_3d_draw_functions: Res<DrawFunctions<Opaque3d>>,
     custom_pipeline: Res<CustomPipeline>,
     msaa: Res<Msaa>,
     mut pipelines: ResMut<SpecializedMeshPipelines<CustomPipeline>>,
     pipeline_cache: Res<PipelineCache>,
     meshes: Res<RenderAssets<Mesh>>,
-    material_meshes: Query<(Entity, &MeshUniform, &Handle<Mesh>), With<InstanceMaterialData>>,
-    mut views: Query<(&ExtractedView, &mut RenderPhase<Transparent3d>)>,
+    material_meshes: Query<(Entity, &MeshUniform, &Handle<Mesh>), With<Instanced>>,
+    mut views: Query<(&ExtractedView, &mut RenderPhase<Opaque3d>)>,
 ) {
-    let draw_custom = transparent_3d_draw_functions.read().id::<DrawCustom>();
+    let draw_custom = opaque_3d_draw_functions.read().id::<DrawCustom>();
 
     let msaa_key = MeshPipelineKey::from_msaa_samples(msaa.samples());
 
-    for (view, mut transparent_phase) in &mut views {
+    for (view, mut opaque_phase) in &mut views {
         let view_key = msaa_key | MeshPipelineKey::from_hdr(view.hdr);
         let rangefinder = view.rangefinder3d();
         for (entity, mesh_uniform, mesh_handle) in &material_meshes {
@@ -120,7 +98,7 @@ fn queue_custom(
                 let pipeline = pipelines
                     .specialize(&pipeline_cache, &custom_pipeline, key, &mesh.layout)
                     .unwrap();
-                transparent_phase.add(Transparent3d {
+                opaque_phase.add(Opaque3d {
                     entity,
                     pipeline,
                     draw_function: draw_custom,
@@ -131,34 +109,67 @@ fn queue_custom(
     }
 }
 
-#[derive(Component)]
-pub struct InstanceBuffer {
+struct InstanceBuffer {
     buffer: Buffer,
     length: usize,
 }
 
-fn prepare_instance_buffers(
+/// Instance buffers by entity, kept across frames since render world entities are not.
+#[derive(Resource, Default)]
+pub struct InstanceBuffers(HashMap<Entity, InstanceBuffer>);
+
+fn extract_instances(
     mut commands: Commands,
-    query: Query<(Entity, &InstanceMaterialData)>,
+    query: Extract<Query<(Entity, Ref<InstanceMaterialData>, &InstanceMaterial)>>,
+) {
+    for (entity, instances, material) in query.iter() {
+        let mut entity = commands.get_or_spawn(entity);
+        entity.insert((Instanced, material.clone()));
+        if instances.is_changed() {
+            entity.insert(ChangedInstances(instances.0.clone()));
+        }
+    }
+}
+
+fn prepare_instance_buffers(
+    query: Query<(Entity, Option<&ChangedInstances>), With<Instanced>>,
+    mut buffers: ResMut<InstanceBuffers>,
     render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
 ) {
-    for (entity, instance_data) in &query {
-        let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-            label: Some("instance data buffer"),
-            contents: bytemuck::cast_slice(instance_data.as_slice()),
-            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
-        });
-        commands.entity(entity).insert(InstanceBuffer {
-            buffer,
-            length: instance_data.len(),
-        });
+    for (entity, changed) in &query {
+        let Some(ChangedInstances(instances)) = changed else {
+            continue;
+        };
+        let contents = bytemuck::cast_slice(instances.as_slice());
+        match buffers.0.get_mut(&entity) {
+            Some(instance_buffer) if instance_buffer.buffer.size() >= contents.len() as u64 => {
+                render_queue.write_buffer(&instance_buffer.buffer, 0, contents);
+                instance_buffer.length = instances.len();
+            }
+            _ if instances.is_empty() => {
+                buffers.0.remove(&entity);
+            }
+            _ => {
+                let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                    label: Some("instance data buffer"),
+                    contents,
+                    usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+                });
+                let length = instances.len();
+                buffers.0.insert(entity, InstanceBuffer { buffer, length });
+            }
+        }
     }
+    // Drop the buffers of despawned entities
+    buffers.0.retain(|entity, _| query.contains(*entity));
 }
 
 #[derive(Resource)]
 pub struct CustomPipeline {
     shader: Handle<Shader>,
     mesh_pipeline: MeshPipeline,
+    material_layout: BindGroupLayout,
 }
 
 impl FromWorld for CustomPipeline {
@@ -167,10 +178,12 @@ impl FromWorld for CustomPipeline {
         let shader = asset_server.load("shaders/instancing.wgsl");
 
         let mesh_pipeline = world.resource::<MeshPipeline>();
+        let material_layout = MainMaterial::bind_group_layout(world.resource::<RenderDevice>());
 
         CustomPipeline {
             shader,
             mesh_pipeline: mesh_pipeline.clone(),
+            material_layout,
         }
     }
 }
@@ -184,6 +197,7 @@ impl SpecializedMeshPipeline for CustomPipeline {
         layout: &MeshVertexBufferLayout,
     ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
         let mut descriptor = self.mesh_pipeline.specialize(key, layout)?;
+        descriptor.layout.push(self.material_layout.clone());
         descriptor.vertex.shader = self.shader.clone();
         descriptor.vertex.buffers.push(VertexBufferLayout {
             array_stride: std::mem::size_of::<InstanceData>() as u64,
@@ -210,28 +224,57 @@ type DrawCustom = (
     SetItemPipeline,
     SetMeshViewBindGroup<0>,
     SetMeshBindGroup<1>,
+    SetInstanceMaterialBindGroup<2>,
     DrawMeshInstanced,
 );
 
+pub struct SetInstanceMaterialBindGroup<const I: usize>;
+
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetInstanceMaterialBindGroup<I> {
+    type Param = SRes<RenderMaterials<MainMaterial>>;
+    type ViewWorldQuery = ();
+    type ItemWorldQuery = Read<InstanceMaterial>;
+
+    #[inline]
+    fn render<'w>(
+        _item: &P,
+        _view: (),
+        material: &'w InstanceMaterial,
+        materials: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        // Not prepared yet while its textures are still loading
+        let Some(material) = materials.into_inner().get(&material.0) else {
+            return RenderCommandResult::Failure;
+        };
+        pass.set_bind_group(I, &material.bind_group, &[]);
+        RenderCommandResult::Success
+    }
+}
+
 pub struct DrawMeshInstanced;
 
 impl<P: PhaseItem> RenderCommand<P> for DrawMeshInstanced {
-    type Param = SRes<RenderAssets<Mesh>>;
+    type Param = (SRes<RenderAssets<Mesh>>, SRes<InstanceBuffers>);
     type ViewWorldQuery = ();
-    type ItemWorldQuery = (Read<Handle<Mesh>>, Read<InstanceBuffer>);
+    type ItemWorldQuery = Read<Handle<Mesh>>;
 
     #[inline]
     fn render<'w>(
-        _item: &P,
+        item: &P,
         _view: (),
-        (mesh_handle, instance_buffer): (&'w Handle<Mesh>, &'w InstanceBuffer),
-        meshes: SystemParamItem<'w, '_, Self::Param>,
+        mesh_handle: &'w Handle<Mesh>,
+        (meshes, buffers): SystemParamItem<'w, '_, Self::Param>,
         pass: &mut TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
         let gpu_mesh = match meshes.into_inner().get(mesh_handle) {
             Some(gpu_mesh) => gpu_mesh,
             None => return RenderCommandResult::Failure,
         };
+        // Nothing to draw until the instances are uploaded, or when there are none
+        let Some(instance_buffer) = buffers.into_inner().0.get(&item.entity()) else {
+            return RenderCommandResult::Success;
+        };
 
         pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
         pass.set_vertex_buffer(1, instance_buffer.buffer.slice(..));
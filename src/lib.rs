@@ -0,0 +1,25 @@
+//! The arena shooter as a library, so the simulation can be driven without a window from
+//! integration tests. `main.rs` adds the rendering, camera and mouse look on top.
+
+pub mod actions;
+pub mod ai;
+pub mod collision;
+pub mod critter;
+pub mod gait;
+pub mod gait_trace;
+pub mod generator;
+pub mod health;
+pub mod ik;
+pub mod instance;
+pub mod main_material;
+pub mod map;
+pub mod movement;
+pub mod nav;
+pub mod projectile;
+pub mod ragdoll;
+pub mod scene;
+pub mod sim;
+pub mod skybox;
+pub mod state;
+pub mod team;
+pub mod weapon;
@@ -1,24 +1,50 @@
 use anyhow::Result;
-use bevy::{
-    core_pipeline::tonemapping::Tonemapping,
-    math::{vec2, Vec3Swizzles},
-    render::render_resource::Extent3d,
-    window::CursorGrabMode,
-};
-use bevy::{input::mouse::MouseMotion, math::vec3, prelude::*};
+use bevy::core_pipeline::tonemapping::Tonemapping;
+use bevy::{math::vec3, prelude::*};
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
-use critter::{make_cirtter, Critter};
-
-use main_material::MainMaterial;
-use rand::{rngs::ThreadRng, thread_rng, Rng};
-const SCENE_LENGTH: usize = 30;
-
-mod critter;
-mod instance;
-mod main_material;
-mod skybox;
+use shooter_game::{
+    actions::{self, ActionState},
+    ai,
+    collision::Collider,
+    critter::{self, make_cirtter, CritterLod, CritterSpec},
+    gait_trace::{self, GaitTraceSettings},
+    generator::GeneratorSettings,
+    health::{self, Health, Respawns},
+    instance,
+    main_material::{self, MainMaterial},
+    map::{self, Arena},
+    movement::Movement,
+    projectile, ragdoll,
+    scene::SceneData,
+    sim::{self, Gimble, MainPlayer, MoveInput, Physics, VIEW_LOCK},
+    skybox,
+    state::{self, GameState},
+    team::Team,
+    weapon::{self, Weapon},
+};
 
 fn main() {
+    let generator = match GeneratorSettings::from_args(std::env::args()) {
+        Ok(generator) => generator,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(2);
+        }
+    };
+    let team = match Team::from_args(std::env::args()) {
+        Ok(team) => team.unwrap_or(Team::Blue),
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(2);
+        }
+    };
+    let gait_trace = match GaitTraceSettings::from_args(std::env::args()) {
+        Ok(gait_trace) => gait_trace,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(2);
+        }
+    };
     App::new()
         .add_plugins(DefaultPlugins.set(AssetPlugin {
             watch_for_changes: true,
@@ -28,120 +54,84 @@ fn main() {
         .add_plugin(critter::CritterPlugin)
         .add_plugin(skybox::SkyboxPlugin)
         .add_plugin(main_material::MainMaterialPlugin)
-        // .add_plugin(instance::CustomMaterialPlugin)
-        // .add_system(instance::setup)
+        .add_plugin(map::MapPlugin)
+        .add_plugin(weapon::WeaponPlugin)
+        .add_plugin(projectile::ProjectilePlugin)
+        .add_plugin(health::HealthPlugin)
+        .add_plugin(ai::AiPlugin)
+        .add_plugin(ragdoll::RagdollPlugin)
+        .add_plugin(gait_trace::GaitTracePlugin(gait_trace))
+        .add_plugin(instance::CustomMaterialPlugin)
+        .add_plugin(actions::ActionsPlugin)
+        .add_plugin(sim::GameSimPlugin)
+        .insert_resource(ArenaGenerator(generator))
+        .insert_resource(PlayerTeam(team))
         .add_startup_system(setup)
-        .add_system(physics)
-        .add_system(keyboard_input)
-        .add_system(update_critter_velocity)
-        // .add_system(cursor_grab_system)
-        .add_system(mouse_motion)
+        .add_plugin(state::GameStatePlugin)
+        .add_system(look_around.run_if(in_state(GameState::Playing)))
         .run();
 }
 
 // fn main() {
 //     instance::main();
 // }
+
+/// The generator asked for on the command line, parsed before the app starts so bad arguments
+/// stop it straight away.
+#[derive(Resource)]
+struct ArenaGenerator(Option<GeneratorSettings>);
+
+/// The side the player picked with `--team`, blue unless asked otherwise.
+#[derive(Resource)]
+struct PlayerTeam(Team);
+
 /// set up a simple 3D scene
 fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<MainMaterial>>,
-    images: ResMut<Assets<Image>>,
+    mut images: ResMut<Assets<Image>>,
+    asset_server: Res<AssetServer>,
+    generator: Res<ArenaGenerator>,
+    team: Res<PlayerTeam>,
 ) {
-    let plane = meshes.add(shape::Plane::from_size(SCENE_LENGTH as f32).into());
-
-    let mut rng = thread_rng();
-    let data: [[f32; SCENE_LENGTH]; SCENE_LENGTH] = random_scene(&mut rng);
-    let box_texture = array_to_texture(images, data);
+    // Stand in for the arena until the map file has loaded, see `map::spawn_arena`
+    let data = SceneData::centered(1, 1, 1.0);
+    let box_texture = images.add(data.to_image());
 
     let white_material = materials.add(MainMaterial {
         color: Color::rgb(1.0, 1.0, 1.0),
         boxes: Some(box_texture.clone()),
-        ..default()
+        map: data.shader_params(),
     });
-
-    let blue_material = materials.add(MainMaterial {
-        color: Color::rgb(0.4, 0.4, 1.0),
+    let block_material = materials.add(MainMaterial {
+        color: Color::rgb(1.0, 1.0, 1.0),
         boxes: Some(box_texture.clone()),
-        ..default()
+        map: data.shader_params(),
     });
 
-    let red_material = materials.add(MainMaterial {
-        color: Color::rgb(1.0, 0.4, 0.4),
-        boxes: Some(box_texture.clone()),
-        ..default()
-    });
-    // plane
-    commands.spawn(MaterialMeshBundle {
-        mesh: plane.clone(),
-        material: blue_material.clone(),
-        transform: Transform::from_xyz(SCENE_LENGTH as f32 * -0.25, 0.0, 0.0)
-            .with_scale(vec3(0.5, 1.0, 1.0)),
-        ..default()
-    });
-    commands.spawn(MaterialMeshBundle {
-        mesh: plane.clone(),
-        material: red_material.clone(),
-        transform: Transform::from_xyz(SCENE_LENGTH as f32 * 0.25, 0.0, 0.0)
-            .with_scale(vec3(0.5, 1.0, 1.0)),
-        ..default()
-    });
-    let player_body = make_cirtter(&mut commands, white_material.clone(), &mut meshes);
-    make_player(&mut commands, &[player_body]);
+    let player_body = make_cirtter(
+        &mut commands,
+        CritterSpec::default(),
+        white_material,
+        &mut meshes,
+    );
+    make_player(&mut commands, &[player_body], team.0);
     // make_player(&mut commands, &[]);
 
-    let mesh = meshes.add(Mesh::from(shape::Cube { size: 1.001 }));
-    place_cubes(mesh, data, &mut commands, white_material);
-
-    commands.insert_resource(SceneData { blocks: data });
-}
-
-fn update_critter_velocity(
-    physics_havers: Query<(&Children, &Physics)>,
-    mut critters: Query<&mut Critter>,
-) {
-    for (children, physics) in physics_havers.iter() {
-        for child in children {
-            let _ = critters
-                .get_mut(*child)
-                .map(|mut crit| crit.velocity = physics.velocity);
-        }
-    }
-}
-
-fn place_cubes(
-    mesh: Handle<Mesh>,
-    data: [[f32; SCENE_LENGTH]; SCENE_LENGTH],
-    commands: &mut Commands,
-    material: Handle<MainMaterial>,
-) {
-    for x in 0..SCENE_LENGTH {
-        for y in 0..SCENE_LENGTH {
-            let pos = (vec2(x as f32, y as f32) / SCENE_LENGTH as f32 - 0.5) * SCENE_LENGTH as f32;
-            let value = data[x][y];
-            if value > 0.6 {
-                let box_height = value * 0.5;
-                commands.spawn((
-                    Cube {
-                        x: x as usize,
-                        y: y as usize,
-                    },
-                    MaterialMeshBundle {
-                        mesh: mesh.clone(),
-
-                        material: material.clone(),
-                        transform: Transform::from_xyz(pos.x + 0.5, box_height, pos.y + 0.5)
-                            .with_scale(vec3(1.0, value, 1.0)),
-                        ..default()
-                    },
-                ));
-            };
-        }
-    }
+    commands.insert_resource(Arena {
+        map: asset_server.load("maps/arena.map.ron"),
+        box_texture,
+        block_material,
+        floor_materials: Vec::new(),
+        floor_mesh: meshes.add(shape::Plane::from_size(1.0).into()),
+        block_mesh: meshes.add(map::block_mesh(data.cell_size())),
+        generator: generator.0,
+    });
+    commands.insert_resource(data);
 }
 
-fn make_player(commands: &mut Commands, children: &[Entity]) {
+fn make_player(commands: &mut Commands, children: &[Entity], team: Team) {
     let camera_id = commands
         .spawn(Camera3dBundle {
             camera: Camera {
@@ -152,6 +142,7 @@ fn make_player(commands: &mut Commands, children: &[Entity]) {
             transform: Transform::from_xyz(0.0, 0.0, 0.5).looking_at(-Vec3::Z, Vec3::Y),
             ..default()
         })
+        .insert((Weapon::default(), CritterLod::default()))
         .id();
     // player
     let gimble_id = commands
@@ -169,7 +160,13 @@ fn make_player(commands: &mut Commands, children: &[Entity]) {
 
     let player = commands
         .spawn((
-            Physics::default(),
+            Physics::at(vec3(0.0, 1.0, 0.0)),
+            Collider::default(),
+            MoveInput::default(),
+            Movement::default(),
+            Health::new(100.0),
+            Respawns,
+            team,
             TransformBundle {
                 local: Transform::from_xyz(0.0, 1., 0.0),
                 ..default()
@@ -190,35 +187,6 @@ fn make_player(commands: &mut Commands, children: &[Entity]) {
 
 // )
 
-fn array_to_texture(
-    mut images: ResMut<Assets<Image>>,
-    data: [[f32; SCENE_LENGTH]; SCENE_LENGTH],
-) -> Handle<Image> {
-    let box_texture = images.add(Image::new(
-        Extent3d {
-            width: SCENE_LENGTH as u32,
-            height: SCENE_LENGTH as u32,
-            depth_or_array_layers: 1,
-        },
-        bevy::render::render_resource::TextureDimension::D2,
-        {
-            let coords = 0..SCENE_LENGTH;
-            coords
-                .clone()
-                .flat_map(|x| coords.clone().map(move |y| (x, y)))
-                .flat_map(|(x, y)| data[y][x].to_ne_bytes())
-                .collect()
-        },
-        bevy::render::render_resource::TextureFormat::R32Float,
-    ));
-    box_texture
-}
-
-#[derive(Debug, Component, Reflect, Clone, Copy)]
-struct Cube {
-    x: usize,
-    y: usize,
-}
 // fn gen_nearest(
 //     data: [[f32; SCENE_LENGTH]; SCENE_LENGTH],
 // ) -> [[(usize, usize); SCENE_LENGTH]; SCENE_LENGTH] {
@@ -242,166 +210,23 @@ struct Cube {
 //     result
 // }
 
-fn random_scene(rng: &mut ThreadRng) -> [[f32; SCENE_LENGTH]; SCENE_LENGTH] {
-    let mut data = [[0.0; SCENE_LENGTH]; SCENE_LENGTH];
-    for tile in data.iter_mut().map(|slice| slice.iter_mut()).flatten() {
-        *tile = rng.gen_range(0.0..1.0);
-    }
-    data
-}
-
-#[derive(Resource, Debug, Reflect)]
-struct SceneData<const I: usize> {
-    blocks: [[f32; I]; I],
-}
-
-#[derive(Reflect, Debug, Default, Component)]
-struct Physics {
-    velocity: Vec3,
-    on_ground: bool,
-}
-
-#[derive(Resource, Reflect, Debug)]
-struct MainPlayer {
-    id: Entity,
-    gimble_id: Entity,
-    camera_id: Entity,
-}
-
-#[derive(Reflect, Debug, Default, Component)]
-struct Gimble {
-    theta: f32,
-}
-
-fn physics(
-    mut players: Query<(&mut Transform, &mut Physics)>,
-    time: Res<Time>,
-    data: Res<SceneData<SCENE_LENGTH>>,
-) {
-    let delta = time.delta_seconds();
-    for (mut transform, mut physics) in players.iter_mut() {
-        do_scene_colisions(&mut transform, &mut physics, &data);
-        physics.velocity.y -= delta * 9.81;
-        transform.translation += physics.velocity * delta;
-        if transform.translation.y <= 0.0 {
-            physics.velocity.y = 0.0;
-            physics.velocity.x *= 0.7;
-            physics.velocity.z *= 0.7;
-            transform.translation.y = 0.0;
-            physics.on_ground = true;
-        }
-    }
-}
-
-fn do_scene_colisions(
-    transform: &mut Transform,
-    physics: &mut Physics,
-    data: &Res<SceneData<SCENE_LENGTH>>,
-) {
-    let grid_coord =
-        Vec3Swizzles::xz(transform.translation) / SCENE_LENGTH as f32 + 0.5 * SCENE_LENGTH as f32;
-    for (x, y) in (-1..1).flat_map(|x| (-1..1).map(move |y| (x, y))) {
-        let cube_coord = vec2(x as f32, y as f32) + grid_coord;
-        let cube_position = vec2(cube_coord.x as f32, cube_coord.y as f32) - 0.5;
-        if (transform.translation.x - cube_position.x).abs() - 0.6 < 0.0
-            && (transform.translation.y - cube_position.y).abs() - 0.6 < 0.0
-        {
-            physics.velocity = physics.velocity
-                * -physics
-                    .velocity
-                    .dot(cube_position.extend(0.0).xzy() - transform.translation);
-        }
-    }
-}
-
-fn keyboard_input(
-    keys: Res<Input<KeyCode>>,
-    main_player: Res<MainPlayer>,
-    mut player: Query<(&mut Physics, &Transform)>,
-    time: Res<Time>,
-) {
-    let _ = || -> Result<()> {
-        let (mut physics, transform) = player.get_mut(main_player.id)?;
-        let mut vel = Vec3::ZERO;
-        let delta = time.delta_seconds();
-        let speed = if physics.on_ground {
-            if keys.any_pressed([KeyCode::LShift, KeyCode::RShift]) {
-                2.0
-            } else {
-                1.0
-            }
-        } else {
-            0.2
-        } * 20.0;
-        if keys.pressed(KeyCode::W) {
-            vel += transform.forward();
-        }
-        if keys.pressed(KeyCode::A) {
-            vel += transform.left() * 0.5;
-        }
-        if keys.pressed(KeyCode::S) {
-            vel += transform.back();
-        }
-        if keys.pressed(KeyCode::D) {
-            vel += transform.right() * 0.5;
-        }
-        physics.velocity += vel * delta * speed;
-
-        if keys.just_pressed(KeyCode::Space) {
-            if physics.on_ground {
-                physics.velocity.y += 2.0; // Space was pressed
-                physics.on_ground = false;
-            }
-        }
-        if keys.any_just_pressed([KeyCode::Delete, KeyCode::Back]) {
-            // Either delete or backspace was just pressed
-        }
-        if keys.just_released(KeyCode::LControl) {
-            // Left Ctrl was released
-        }
-        Ok(())
-    }();
-}
-
-fn mouse_motion(
-    mut motion_evr: EventReader<MouseMotion>,
+fn look_around(
+    actions: Res<ActionState>,
     player: Res<MainPlayer>,
     mut transform: Query<&mut Transform>,
     mut gimble: Query<&mut Gimble>,
 ) {
+    let look = actions.look();
+    if look == Vec2::ZERO {
+        return;
+    }
     let _ = || -> Result<()> {
         let [mut player_transform, mut gimble_transform] =
             transform.get_many_mut([player.id, player.gimble_id])?;
         let mut gimble = gimble.get_mut(player.gimble_id)?;
-        for ev in motion_evr.iter() {
-            // println!("Mouse moved: X: {} px, Y: {} px", ev.delta.x, ev.delta.y);
-            const SENCITIVITY: f32 = 0.01;
-            player_transform.rotate_y(ev.delta.x * -SENCITIVITY);
-            let view_lock = 1.5707963268;
-            gimble.theta = (gimble.theta + ev.delta.y * -SENCITIVITY)
-                .min(view_lock)
-                .max(-view_lock);
-            gimble_transform.rotation = Quat::from_rotation_x(gimble.theta);
-        }
-
+        player_transform.rotate_y(look.x);
+        gimble.theta = (gimble.theta + look.y).clamp(-VIEW_LOCK, VIEW_LOCK);
+        gimble_transform.rotation = Quat::from_rotation_x(gimble.theta);
         Ok(())
     }();
 }
-
-fn cursor_grab_system(
-    mut windows: Query<&mut Window>,
-    btn: Res<Input<MouseButton>>,
-    key: Res<Input<KeyCode>>,
-) {
-    let mut window = windows.get_single_mut().unwrap();
-
-    if btn.just_pressed(MouseButton::Left) {
-        window.cursor.grab_mode = CursorGrabMode::Locked;
-        window.cursor.visible = false;
-    }
-
-    if key.just_pressed(KeyCode::Escape) {
-        window.cursor.grab_mode = CursorGrabMode::None;
-        window.cursor.visible = true;
-    }
-}
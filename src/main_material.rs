@@ -32,4 +32,7 @@ pub struct MainMaterial {
     #[texture(1)]
     #[sampler(2)]
     pub boxes: Option<Handle<Image>>,
+    /// Layout of the `boxes` heightfield, see [`crate::scene::SceneData::shader_params`].
+    #[uniform(3)]
+    pub map: Vec4,
 }
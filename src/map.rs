@@ -0,0 +1,569 @@
+//! Arena map files: a RON description of the block heights, team halves, spawn points and
+//! colors, loaded through the asset server so edits are picked up while the game runs.
+
+use anyhow::{bail, Result};
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    math::{vec2, vec3},
+    prelude::*,
+    reflect::TypeUuid,
+    render::view::NoFrustumCulling,
+    utils::BoxedFuture,
+};
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    generator::GeneratorSettings,
+    instance::{InstanceData, InstanceMaterial, InstanceMaterialData},
+    main_material::MainMaterial,
+    nav::is_open,
+    scene::{SceneData, BLOCK_THRESHOLD},
+    sim::{MainPlayer, Physics},
+    team::Team,
+};
+
+/// Where [`save_map`] writes the current arena, relative to the working directory.
+const SAVE_PATH: &str = "assets/maps/saved.map.ron";
+
+pub struct MapPlugin;
+impl Plugin for MapPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<MapFile>()
+            .init_asset_loader::<MapLoader>()
+            .add_system(spawn_arena)
+            .add_system(save_map);
+    }
+}
+
+/// A rectangle of floor belonging to one team, rendered as a colored plane.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TeamHalf {
+    pub team: Team,
+    pub color: [f32; 3],
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+
+impl TeamHalf {
+    pub fn rect(&self) -> Rect {
+        Rect::from_corners(Vec2::from(self.min), Vec2::from(self.max))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpawnPoint {
+    pub team: Team,
+    pub position: [f32; 3],
+}
+
+/*
+(
+    cell_size: 1.0,
+    block_color: (1.0, 1.0, 1.0),
+    halves: [(team: Blue, color: (0.4, 0.4, 1.0), min: (-15.0, -15.0), max: (0.0, 15.0))],
+    spawns: [(team: Blue, position: (-12.0, 0.0, 0.0))],
+    heights: [
+        ".   .   1.2 .",
+        ".   0.8 1.2 .",
+    ],
+)
+*/
+#[derive(Debug, Clone, Serialize, Deserialize, TypeUuid)]
+#[uuid = "0b3c58a2-4f7e-4d0c-9a41-6f2d1c9e8a57"]
+pub struct MapFile {
+    pub cell_size: f32,
+    /// World position of the map's minimum corner, the map is centered when left out.
+    #[serde(default)]
+    pub origin: Option<[f32; 2]>,
+    pub block_color: [f32; 3],
+    pub halves: Vec<TeamHalf>,
+    pub spawns: Vec<SpawnPoint>,
+    /// One string per row along +z, cells along +x separated by whitespace, `.` is open floor.
+    pub heights: Vec<String>,
+}
+
+impl MapFile {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        Ok(ron::de::from_bytes(bytes)?)
+    }
+
+    pub fn to_ron(&self) -> Result<String> {
+        Ok(ron::ser::to_string_pretty(
+            self,
+            ron::ser::PrettyConfig::default(),
+        )?)
+    }
+
+    pub fn scene_data(&self) -> Result<SceneData> {
+        let rows = self
+            .heights
+            .iter()
+            .map(|row| {
+                row.split_whitespace()
+                    .map(|cell| match cell {
+                        "." => Ok(0.0),
+                        _ => Ok(cell.parse::<f32>()?),
+                    })
+                    .collect::<Result<Vec<f32>>>()
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let width = rows.first().map_or(0, Vec::len);
+        if let Some(z) = rows.iter().position(|row| row.len() != width) {
+            bail!("row {z} has {} cells, expected {width}", rows[z].len());
+        }
+        let depth = rows.len();
+        let mut data = match self.origin {
+            Some(origin) => SceneData::new(width, depth, self.cell_size, Vec2::from(origin)),
+            None => SceneData::centered(width, depth, self.cell_size),
+        };
+        for (z, row) in rows.iter().enumerate() {
+            for (x, height) in row.iter().enumerate() {
+                data.set(x, z, *height);
+            }
+        }
+        Ok(data)
+    }
+
+    pub fn half(&self, team: Team) -> Option<&TeamHalf> {
+        self.halves.iter().find(|half| half.team == team)
+    }
+
+    /// The third of `team`'s half farthest from the other halves, where it spawns when the map
+    /// doesn't place spawn points itself.
+    pub fn spawn_zone(&self, team: Team) -> Option<Rect> {
+        let rect = self.half(team)?.rect();
+        let others = self
+            .halves
+            .iter()
+            .filter(|half| half.team != team)
+            .map(|half| half.rect().center())
+            .collect::<Vec<_>>();
+        if others.is_empty() {
+            return Some(rect);
+        }
+        let away = rect.center() - others.iter().sum::<Vec2>() / others.len() as f32;
+        let depth = rect.size() / 3.0;
+        let (mut min, mut max) = (rect.min, rect.max);
+        if away.x.abs() >= away.y.abs() {
+            if away.x < 0.0 {
+                max.x = min.x + depth.x;
+            } else {
+                min.x = max.x - depth.x;
+            }
+        } else if away.y < 0.0 {
+            max.y = min.y + depth.y;
+        } else {
+            min.y = max.y - depth.y;
+        }
+        Some(Rect { min, max })
+    }
+
+    /// A random spawn point belonging to `team`, or a random open cell of its
+    /// [`spawn_zone`](Self::spawn_zone) when the map has none. `None` when the map has neither
+    /// spawn points nor an open cell on the team's half.
+    pub fn spawn_point(&self, team: Team, data: &SceneData, rng: &mut impl Rng) -> Option<Vec3> {
+        let spawns = self
+            .spawns
+            .iter()
+            .filter(|spawn| spawn.team == team)
+            .collect::<Vec<_>>();
+        if !spawns.is_empty() {
+            return Some(Vec3::from(spawns[rng.gen_range(0..spawns.len())].position));
+        }
+        let open_cells = |area: Rect| {
+            data.cells()
+                .filter(|&(x, z)| is_open(data, (x, z)) && area.contains(data.cell_center(x, z)))
+                .collect::<Vec<_>>()
+        };
+        // Blocks can fill the whole zone, then anywhere open on the half will do
+        let mut cells = open_cells(self.spawn_zone(team)?);
+        if cells.is_empty() {
+            cells = open_cells(self.half(team)?.rect());
+        }
+        if cells.is_empty() {
+            return None;
+        }
+        let (x, z) = cells[rng.gen_range(0..cells.len())];
+        let position = data.cell_center(x, z);
+        Some(vec3(position.x, 0.0, position.y))
+    }
+
+    /// The heights of `data` with everything else taken from `template`.
+    pub fn from_scene(data: &SceneData, template: &MapFile) -> Self {
+        let cells = (0..data.depth())
+            .map(|z| {
+                (0..data.width())
+                    .map(|x| match data.get(x, z) {
+                        h if h <= 0.0 => ".".to_string(),
+                        h => h.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let column = cells.iter().flatten().map(String::len).max().unwrap_or(1);
+        let heights = cells
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|cell| format!("{cell:<column$}"))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+                    .trim_end()
+                    .to_string()
+            })
+            .collect();
+        Self {
+            cell_size: data.cell_size(),
+            origin: Some(data.origin().to_array()),
+            heights,
+            ..template.clone()
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct MapLoader;
+
+impl AssetLoader for MapLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<()>> {
+        Box::pin(async move {
+            load_context.set_default_asset(LoadedAsset::new(MapFile::parse(bytes)?));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["map.ron"]
+    }
+}
+
+/// The map currently in play and the assets that are rebuilt when it changes.
+#[derive(Resource)]
+pub struct Arena {
+    pub map: Handle<MapFile>,
+    pub box_texture: Handle<Image>,
+    pub block_material: Handle<MainMaterial>,
+    /// One per team half in map order, reused when the map is reloaded.
+    pub floor_materials: Vec<Handle<MainMaterial>>,
+    /// Unit square every team half's plane is scaled from.
+    pub floor_mesh: Handle<Mesh>,
+    /// One block, see [`block_mesh`], rewritten in place when the cell size changes.
+    pub block_mesh: Handle<Mesh>,
+    /// Replaces the map file's heights with a generated layout of the same size.
+    pub generator: Option<GeneratorSettings>,
+}
+
+/// Marks entities that are despawned when the arena is rebuilt.
+#[derive(Component)]
+pub struct ArenaPiece;
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_arena(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<MapFile>>,
+    mut arena: ResMut<Arena>,
+    maps: Res<Assets<MapFile>>,
+    pieces: Query<Entity, With<ArenaPiece>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<MainMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+    player: Res<MainPlayer>,
+    mut bodies: Query<(&mut Physics, &Team)>,
+) {
+    let mut created = false;
+    let mut modified = false;
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle } if *handle == arena.map => created = true,
+            AssetEvent::Modified { handle } if *handle == arena.map => modified = true,
+            _ => {}
+        }
+    }
+    if !created && !modified {
+        return;
+    }
+    let Some(map) = maps.get(&arena.map) else {
+        return;
+    };
+    let mut data = match map.scene_data() {
+        Ok(data) => data,
+        Err(err) => {
+            error!("invalid map heights: {err}");
+            return;
+        }
+    };
+    if let Some(settings) = &arena.generator {
+        info!(
+            "generating {:?} arena from seed {}",
+            settings.kind, settings.seed
+        );
+        settings.generate(&mut data);
+        clear_spawns(&mut data, &map.spawns);
+    }
+
+    for entity in pieces.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    images.set_untracked(&arena.box_texture, data.to_image());
+    let map_params = data.shader_params();
+    for (_, material) in materials.iter_mut() {
+        material.map = map_params;
+    }
+    let [r, g, b] = map.block_color;
+    if let Some(material) = materials.get_mut(&arena.block_material) {
+        material.color = Color::rgb(r, g, b);
+    }
+
+    let box_texture = arena.box_texture.clone();
+    arena.floor_materials.truncate(map.halves.len());
+    while arena.floor_materials.len() < map.halves.len() {
+        arena.floor_materials.push(materials.add(MainMaterial {
+            color: Color::WHITE,
+            boxes: Some(box_texture.clone()),
+            map: map_params,
+        }));
+    }
+    for (half, material) in map.halves.iter().zip(&arena.floor_materials) {
+        let (min, max) = (Vec2::from(half.min), Vec2::from(half.max));
+        let center = (min + max) * 0.5;
+        let [r, g, b] = half.color;
+        if let Some(material) = materials.get_mut(material) {
+            material.color = Color::rgb(r, g, b);
+        }
+        commands.spawn((
+            ArenaPiece,
+            MaterialMeshBundle {
+                mesh: arena.floor_mesh.clone(),
+                material: material.clone(),
+                transform: Transform::from_xyz(center.x, 0.0, center.y).with_scale(vec3(
+                    max.x - min.x,
+                    1.0,
+                    max.y - min.y,
+                )),
+                ..default()
+            },
+        ));
+    }
+
+    meshes.set_untracked(&arena.block_mesh, block_mesh(data.cell_size()));
+    commands.spawn((
+        ArenaPiece,
+        arena.block_mesh.clone(),
+        SpatialBundle::INHERITED_IDENTITY,
+        InstanceMaterialData(block_instances(&data, map)),
+        InstanceMaterial(arena.block_material.clone()),
+        // Blocks are spread over the whole map, far from the entity's own transform
+        NoFrustumCulling,
+    ));
+
+    // Only move the player on the first load so editing the map doesn't teleport them
+    if created {
+        if let Ok((mut physics, team)) = bodies.get_mut(player.id) {
+            if let Some(spawn) = map.spawn_point(*team, &data, &mut thread_rng()) {
+                physics.teleport(spawn);
+            }
+        }
+    }
+    commands.insert_resource(data);
+}
+
+/// A block one `cell_size` across and one unit tall with its base at the origin, each instance
+/// stretches it to its block's height.
+pub fn block_mesh(cell_size: f32) -> Mesh {
+    let half_size = cell_size * 1.001 * 0.5;
+    Mesh::from(shape::Box {
+        min_x: -half_size,
+        max_x: half_size,
+        min_y: 0.0,
+        max_y: 1.0,
+        min_z: -half_size,
+        max_z: half_size,
+    })
+}
+
+/// How much of its team's color a block on that team's half takes on.
+const TEAM_TINT: f32 = 0.5;
+
+/// One instance per block, tinted by the team half it stands on. The block color itself
+/// comes from the block material.
+fn block_instances(data: &SceneData, map: &MapFile) -> Vec<InstanceData> {
+    data.cells()
+        .filter(|&(x, z)| data.get(x, z) > BLOCK_THRESHOLD)
+        .map(|(x, z)| {
+            let pos = data.cell_center(x, z);
+            let half = map.halves.iter().find(|half| half.rect().contains(pos));
+            let color = match half {
+                Some(half) => Vec3::ONE.lerp(Vec3::from(half.color), TEAM_TINT),
+                None => Vec3::ONE,
+            };
+            InstanceData {
+                position: vec3(pos.x, 0.0, pos.y),
+                height: data.get(x, z),
+                color: color.extend(1.0).to_array(),
+            }
+        })
+        .collect()
+}
+
+/// Open up the cells around each spawn point so generated blocks can't trap anyone.
+fn clear_spawns(data: &mut SceneData, spawns: &[SpawnPoint]) {
+    let cells = data.cells().collect::<Vec<_>>();
+    for spawn in spawns {
+        let position = vec2(spawn.position[0], spawn.position[2]);
+        for (x, z) in cells.iter().copied() {
+            if data.cell_center(x, z).distance(position) < 1.5 * data.cell_size() {
+                data.set(x, z, 0.0);
+            }
+        }
+    }
+}
+
+fn save_map(
+    keys: Res<Input<KeyCode>>,
+    arena: Res<Arena>,
+    maps: Res<Assets<MapFile>>,
+    data: Res<SceneData>,
+) {
+    if !keys.just_pressed(KeyCode::F5) {
+        return;
+    }
+    let Some(template) = maps.get(&arena.map) else {
+        return;
+    };
+    let result = MapFile::from_scene(&data, template)
+        .to_ron()
+        .and_then(|ron| Ok(std::fs::write(SAVE_PATH, ron)?));
+    match result {
+        Ok(()) => info!("saved map to {SAVE_PATH}"),
+        Err(err) => error!("failed to save map: {err}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn two_halves() -> MapFile {
+        MapFile::parse(
+            br#"(
+                cell_size: 1.0,
+                block_color: (1.0, 1.0, 1.0),
+                halves: [
+                    (team: Blue, color: (0.4, 0.4, 1.0), min: (-3.0, -1.0), max: (0.0, 1.0)),
+                    (team: Red, color: (1.0, 0.4, 0.4), min: (0.0, -1.0), max: (3.0, 1.0)),
+                ],
+                spawns: [],
+                heights: [
+                    "1 . . . . .",
+                    ". . . . . 1",
+                ],
+            )"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn saved_maps_load_back_the_same() {
+        let mut template = two_halves();
+        template.spawns.push(SpawnPoint {
+            team: Team::Blue,
+            position: [-2.5, 0.0, 0.5],
+        });
+        let mut data = SceneData::new(3, 2, 2.0, vec2(-4.0, -1.0));
+        data.set(0, 0, 1.25);
+        data.set(2, 1, 0.8);
+        let saved = MapFile::from_scene(&data, &template);
+        let loaded = MapFile::parse(saved.to_ron().unwrap().as_bytes()).unwrap();
+
+        let loaded_data = loaded.scene_data().unwrap();
+        assert_eq!(
+            (loaded_data.width(), loaded_data.depth()),
+            (data.width(), data.depth())
+        );
+        assert_eq!(loaded_data.cell_size(), data.cell_size());
+        assert_eq!(loaded_data.origin(), data.origin());
+        for (x, z) in data.cells() {
+            assert_eq!(loaded_data.get(x, z), data.get(x, z), "cell {x} {z}");
+        }
+        assert_eq!(loaded.block_color, template.block_color);
+        assert_eq!(loaded.halves.len(), template.halves.len());
+        for (loaded, template) in loaded.halves.iter().zip(&template.halves) {
+            assert_eq!(loaded.team, template.team);
+            assert_eq!(loaded.color, template.color);
+            assert_eq!((loaded.min, loaded.max), (template.min, template.max));
+        }
+        assert_eq!(loaded.spawns.len(), template.spawns.len());
+        for (loaded, template) in loaded.spawns.iter().zip(&template.spawns) {
+            assert_eq!(loaded.team, template.team);
+            assert_eq!(loaded.position, template.position);
+        }
+    }
+
+    #[test]
+    fn spawn_zones_are_at_the_far_end_of_each_half() {
+        let map = two_halves();
+        let blue = map.spawn_zone(Team::Blue).unwrap();
+        assert_eq!((blue.min, blue.max), (vec2(-3.0, -1.0), vec2(-2.0, 1.0)));
+        let red = map.spawn_zone(Team::Red).unwrap();
+        assert_eq!((red.min, red.max), (vec2(2.0, -1.0), vec2(3.0, 1.0)));
+    }
+
+    #[test]
+    fn spawning_without_spawn_points_avoids_blocks() {
+        let map = two_halves();
+        let data = map.scene_data().unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..20 {
+            assert_eq!(
+                map.spawn_point(Team::Blue, &data, &mut rng),
+                Some(vec3(-2.5, 0.0, 0.5))
+            );
+            assert_eq!(
+                map.spawn_point(Team::Red, &data, &mut rng),
+                Some(vec3(2.5, 0.0, -0.5))
+            );
+        }
+
+        // A filled zone falls back to the rest of the half
+        let mut data = data;
+        data.set(0, 1, 1.0);
+        for _ in 0..20 {
+            let spawn = map.spawn_point(Team::Blue, &data, &mut rng).unwrap();
+            let cell = data.cell_at(vec2(spawn.x, spawn.z)).unwrap();
+            assert!(is_open(&data, cell), "{spawn}");
+            assert!(map
+                .half(Team::Blue)
+                .unwrap()
+                .rect()
+                .contains(vec2(spawn.x, spawn.z)));
+        }
+
+        // And a half with nowhere open has nowhere to spawn
+        for (x, z) in data.cells().collect::<Vec<_>>() {
+            data.set(x, z, 1.0);
+        }
+        assert_eq!(map.spawn_point(Team::Blue, &data, &mut rng), None);
+    }
+
+    #[test]
+    fn spawn_points_win_over_the_zone() {
+        let mut map = two_halves();
+        map.spawns.push(SpawnPoint {
+            team: Team::Red,
+            position: [1.0, 0.0, 0.0],
+        });
+        let data = map.scene_data().unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        assert_eq!(
+            map.spawn_point(Team::Red, &data, &mut rng),
+            Some(vec3(1.0, 0.0, 0.0))
+        );
+    }
+}
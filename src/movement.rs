@@ -0,0 +1,481 @@
+//! Quake style movement for bodies driven by a [`MoveInput`]. Every fixed step, friction slows
+//! a grounded body, then it accelerates towards the wished direction until it reaches the
+//! wished speed along it. In the air the wished speed is capped far lower while acceleration
+//! is not, so turning into a strafe keeps adding speed sideways. Crouching shrinks the
+//! collider, crouching while running starts a slide with little friction, and jumping in the
+//! air next to a block kicks off it.
+//!
+//! All the numbers are in [`MovementTunables`], editable from the inspector.
+
+use bevy::{math::Vec3Swizzles, prelude::*};
+
+use crate::{
+    collision::{wall_normal, Collider},
+    scene::SceneData,
+    sim::{physics, Gimble, MainPlayer, MoveInput, Physics},
+};
+
+/// Where the eyes sit as a fraction of the collider's height.
+const EYE_HEIGHT: f32 = 2.0 / 3.0;
+/// How quickly the view follows crouching and standing, per second.
+const EYE_RATE: f32 = 12.0;
+
+pub struct MovementPlugin;
+impl Plugin for MovementPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<MovementTunables>()
+            .register_type::<Movement>()
+            .init_resource::<MovementTunables>()
+            .add_system(
+                apply_move_input
+                    .before(physics)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            )
+            .add_system(crouch_view.run_if(resource_exists::<MainPlayer>()));
+    }
+}
+
+#[derive(Resource, Reflect, Debug, Clone)]
+#[reflect(Resource)]
+pub struct MovementTunables {
+    /// Top running speed, in units per second.
+    pub max_speed: f32,
+    pub sprint_speed: f32,
+    pub crouch_speed: f32,
+    /// How fast speed builds towards the wished speed on the ground, in wished speeds added
+    /// per second.
+    pub ground_accel: f32,
+    pub air_accel: f32,
+    /// Cap on the wished speed in the air, low so air control comes from strafing.
+    pub air_wish_speed: f32,
+    /// Fraction of the speed lost per second on the ground.
+    pub friction: f32,
+    /// Below this speed friction acts as if moving this fast, so bodies come to a full stop.
+    pub stop_speed: f32,
+    pub jump_speed: f32,
+    /// Collider height while crouched, as a fraction of the standing height.
+    pub crouch_scale: f32,
+    /// Horizontal speed needed to slide instead of crouch.
+    pub slide_min_speed: f32,
+    /// Speed added along the direction of travel when a slide starts.
+    pub slide_boost: f32,
+    /// Seconds after a boosted slide before the next slide is boosted, so tapping crouch
+    /// can't keep adding speed.
+    pub slide_cooldown: f32,
+    pub slide_friction: f32,
+    /// How close a block side has to be to jump off it.
+    pub wall_reach: f32,
+    /// Speed away from the wall and upwards given by a wall jump.
+    pub wall_jump_push: f32,
+    pub wall_jump_speed: f32,
+    /// Seconds before another wall jump, so one press can't climb a wall.
+    pub wall_jump_cooldown: f32,
+}
+
+impl Default for MovementTunables {
+    fn default() -> Self {
+        Self {
+            max_speed: 1.5,
+            sprint_speed: 2.5,
+            crouch_speed: 0.6,
+            ground_accel: 10.0,
+            air_accel: 10.0,
+            air_wish_speed: 0.15,
+            friction: 6.0,
+            stop_speed: 0.5,
+            jump_speed: 2.5,
+            crouch_scale: 0.6,
+            slide_min_speed: 1.8,
+            slide_boost: 0.6,
+            slide_cooldown: 1.0,
+            slide_friction: 0.8,
+            wall_reach: 0.05,
+            wall_jump_push: 1.5,
+            wall_jump_speed: 2.5,
+            wall_jump_cooldown: 0.25,
+        }
+    }
+}
+
+/// Movement state of a body driven by a [`MoveInput`].
+#[derive(Component, Reflect, Debug, Clone, Default)]
+pub struct Movement {
+    pub crouched: bool,
+    pub sliding: bool,
+    /// Collider height to return to when standing up.
+    standing_height: f32,
+    /// Seconds until a slide is boosted again.
+    slide_timer: f32,
+    /// Seconds until the next wall jump is allowed.
+    wall_jump_timer: f32,
+}
+
+fn apply_move_input(
+    mut bodies: Query<(&mut Physics, &mut MoveInput, &mut Movement, &mut Collider)>,
+    fixed_time: Res<FixedTime>,
+    tunables: Res<MovementTunables>,
+    data: Res<SceneData>,
+) {
+    let delta = fixed_time.period.as_secs_f32();
+    for (mut physics, mut input, mut movement, mut collider) in bodies.iter_mut() {
+        step(
+            &tunables,
+            &data,
+            delta,
+            &mut physics,
+            &mut input,
+            &mut movement,
+            &mut collider,
+        );
+    }
+}
+
+fn step(
+    tunables: &MovementTunables,
+    data: &SceneData,
+    delta: f32,
+    physics: &mut Physics,
+    input: &mut MoveInput,
+    movement: &mut Movement,
+    collider: &mut Collider,
+) {
+    let on_ground = physics.on_ground;
+    let horizontal = physics.velocity.xz();
+    movement.wall_jump_timer = (movement.wall_jump_timer - delta).max(0.0);
+    movement.slide_timer = (movement.slide_timer - delta).max(0.0);
+
+    if !movement.crouched {
+        movement.standing_height = collider.height;
+    }
+    if input.crouch && !movement.crouched {
+        movement.crouched = true;
+        collider.height = movement.standing_height * tunables.crouch_scale;
+        if on_ground && horizontal.length() >= tunables.slide_min_speed {
+            movement.sliding = true;
+            if movement.slide_timer == 0.0 {
+                let boost = horizontal.normalize() * tunables.slide_boost;
+                physics.velocity += boost.extend(0.0).xzy();
+                movement.slide_timer = tunables.slide_cooldown;
+            }
+        }
+    } else if !input.crouch && movement.crouched {
+        // Blocks rise from the floor, there's never anything overhead to stay crouched under
+        collider.height = movement.standing_height;
+        movement.crouched = false;
+        movement.sliding = false;
+    }
+    if movement.sliding && horizontal.length() < tunables.crouch_speed {
+        movement.sliding = false;
+    }
+
+    if on_ground {
+        let friction = match movement.sliding {
+            true => tunables.slide_friction,
+            false => tunables.friction,
+        };
+        apply_friction(physics, friction, tunables.stop_speed, delta);
+    }
+
+    let wish = (input.direction * Vec3::new(1.0, 0.0, 1.0)).clamp_length_max(1.0);
+    let wish_dir = wish.normalize_or_zero();
+    let top_speed = if movement.crouched {
+        tunables.crouch_speed
+    } else if input.sprint {
+        tunables.sprint_speed
+    } else {
+        tunables.max_speed
+    };
+    let wish_speed = top_speed * wish.length();
+    if !on_ground {
+        let capped = wish_speed.min(tunables.air_wish_speed);
+        accelerate(
+            physics,
+            wish_dir,
+            wish_speed,
+            capped,
+            tunables.air_accel,
+            delta,
+        );
+    } else if !movement.sliding {
+        accelerate(
+            physics,
+            wish_dir,
+            wish_speed,
+            wish_speed,
+            tunables.ground_accel,
+            delta,
+        );
+    }
+
+    if input.jump {
+        if on_ground {
+            physics.velocity.y = tunables.jump_speed;
+            physics.on_ground = false;
+        } else if movement.wall_jump_timer == 0.0 {
+            if let Some(normal) = wall_normal(data, collider, physics.position, tunables.wall_reach)
+            {
+                // Drop whatever was heading into the wall, then kick off it
+                let into = physics.velocity.dot(normal).min(0.0);
+                physics.velocity -= normal * into;
+                physics.velocity += normal * tunables.wall_jump_push;
+                physics.velocity.y = tunables.wall_jump_speed;
+                movement.wall_jump_timer = tunables.wall_jump_cooldown;
+            }
+        }
+    }
+    input.jump = false;
+}
+
+fn apply_friction(physics: &mut Physics, friction: f32, stop_speed: f32, delta: f32) {
+    let speed = physics.velocity.xz().length();
+    if speed == 0.0 {
+        return;
+    }
+    let drop = speed.max(stop_speed) * friction * delta;
+    let scale = (speed - drop).max(0.0) / speed;
+    physics.velocity.x *= scale;
+    physics.velocity.z *= scale;
+}
+
+/*
+    wish_dir
+       ^
+       |   add = cap - velocity . wish_dir
+       |
+       +------------> velocity
+*/
+/// Add speed along `wish_dir` until the velocity's share along it reaches `cap`, at a rate set
+/// by the uncapped `wish_speed`.
+fn accelerate(
+    physics: &mut Physics,
+    wish_dir: Vec3,
+    wish_speed: f32,
+    cap: f32,
+    accel: f32,
+    delta: f32,
+) {
+    let add = cap - physics.velocity.dot(wish_dir);
+    if add <= 0.0 {
+        return;
+    }
+    physics.velocity += wish_dir * (accel * wish_speed * delta).min(add);
+}
+
+/// Lower the view with the player's collider while crouched.
+fn crouch_view(
+    time: Res<Time>,
+    player: Res<MainPlayer>,
+    bodies: Query<&Collider>,
+    mut gimbles: Query<&mut Transform, With<Gimble>>,
+) {
+    let (Ok(collider), Ok(mut transform)) =
+        (bodies.get(player.id), gimbles.get_mut(player.gimble_id))
+    else {
+        return;
+    };
+    let eye = collider.height * EYE_HEIGHT;
+    let follow = (time.delta_seconds() * EYE_RATE).min(1.0);
+    transform.translation.y += (eye - transform.translation.y) * follow;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DELTA: f32 = 1.0 / 60.0;
+
+    struct Body {
+        physics: Physics,
+        input: MoveInput,
+        movement: Movement,
+        collider: Collider,
+    }
+
+    impl Body {
+        fn at(position: Vec3) -> Self {
+            Self {
+                physics: Physics {
+                    on_ground: position.y == 0.0,
+                    ..Physics::at(position)
+                },
+                input: MoveInput::default(),
+                movement: Movement::default(),
+                collider: Collider::default(),
+            }
+        }
+
+        /// One movement step, without moving the body.
+        fn step(&mut self, data: &SceneData) {
+            step(
+                &MovementTunables::default(),
+                data,
+                DELTA,
+                &mut self.physics,
+                &mut self.input,
+                &mut self.movement,
+                &mut self.collider,
+            );
+        }
+
+        fn speed(&self) -> f32 {
+            self.physics.velocity.xz().length()
+        }
+    }
+
+    fn open() -> SceneData {
+        SceneData::new(8, 8, 1.0, Vec2::ZERO)
+    }
+
+    #[test]
+    fn running_tops_out_at_max_speed() {
+        let tunables = MovementTunables::default();
+        let mut body = Body::at(Vec3::new(4.0, 0.0, 4.0));
+        body.input.direction = Vec3::NEG_Z;
+        for _ in 0..120 {
+            body.step(&open());
+        }
+        // Friction takes some off every step before acceleration tops it back up
+        assert!(body.speed() <= tunables.max_speed + 1e-4);
+        assert!(body.speed() > tunables.max_speed * 0.8);
+
+        body.input.sprint = true;
+        for _ in 0..120 {
+            body.step(&open());
+        }
+        assert!(body.speed() > tunables.max_speed);
+        assert!(body.speed() <= tunables.sprint_speed + 1e-4);
+    }
+
+    #[test]
+    fn friction_brings_bodies_to_a_stop() {
+        let mut body = Body::at(Vec3::new(4.0, 0.0, 4.0));
+        body.physics.velocity = Vec3::new(2.0, 0.0, 0.0);
+        for _ in 0..60 {
+            body.step(&open());
+        }
+        assert_eq!(body.speed(), 0.0);
+    }
+
+    #[test]
+    fn air_strafing_gains_speed() {
+        // Turning with the strafe keeps the wished direction square to the velocity
+        let mut body = Body::at(Vec3::new(4.0, 1.0, 4.0));
+        body.physics.velocity = Vec3::new(0.0, 0.0, -1.5);
+        let start = body.speed();
+        for _ in 0..60 {
+            let forward = body.physics.velocity.xz().normalize();
+            body.input.direction = Vec3::new(-forward.y, 0.0, forward.x);
+            body.step(&open());
+        }
+        assert!(body.speed() > start * 1.1, "{}", body.speed());
+
+        // Holding forward alone can't speed up in the air
+        let mut body = Body::at(Vec3::new(4.0, 1.0, 4.0));
+        body.physics.velocity = Vec3::new(0.0, 0.0, -1.5);
+        body.input.direction = Vec3::NEG_Z;
+        for _ in 0..60 {
+            body.step(&open());
+        }
+        assert!(body.speed() <= start + 1e-4);
+    }
+
+    #[test]
+    fn crouching_shrinks_the_collider() {
+        let mut body = Body::at(Vec3::new(4.0, 0.0, 4.0));
+        let standing = body.collider.height;
+        body.input.crouch = true;
+        body.step(&open());
+        assert!(body.movement.crouched);
+        assert!(body.collider.height < standing);
+
+        // Crouched bodies are slow
+        body.input.direction = Vec3::NEG_Z;
+        for _ in 0..60 {
+            body.step(&open());
+        }
+        assert!(body.speed() <= MovementTunables::default().crouch_speed + 1e-4);
+
+        body.input.crouch = false;
+        body.step(&open());
+        assert!(!body.movement.crouched);
+        assert_eq!(body.collider.height, standing);
+    }
+
+    #[test]
+    fn crouching_at_speed_slides() {
+        let tunables = MovementTunables::default();
+        let mut body = Body::at(Vec3::new(4.0, 0.0, 4.0));
+        body.physics.velocity = Vec3::new(0.0, 0.0, -tunables.sprint_speed);
+        body.input.direction = Vec3::NEG_Z;
+        body.input.crouch = true;
+        body.step(&open());
+        assert!(body.movement.sliding);
+        assert!(body.speed() > tunables.sprint_speed);
+
+        // Slides keep going well past crouch speed before running out
+        for _ in 0..30 {
+            body.step(&open());
+        }
+        assert!(body.movement.sliding);
+        assert!(body.speed() > tunables.crouch_speed * 2.0);
+        for _ in 0..600 {
+            body.step(&open());
+        }
+        assert!(!body.movement.sliding);
+
+        // Too slow to slide just crouches
+        let mut body = Body::at(Vec3::new(4.0, 0.0, 4.0));
+        body.physics.velocity = Vec3::new(0.0, 0.0, -1.0);
+        body.input.crouch = true;
+        body.step(&open());
+        assert!(body.movement.crouched);
+        assert!(!body.movement.sliding);
+    }
+
+    #[test]
+    fn tapping_crouch_does_not_stack_slide_boosts() {
+        let tunables = MovementTunables::default();
+        let mut body = Body::at(Vec3::new(4.0, 0.0, 4.0));
+        body.physics.velocity = Vec3::new(0.0, 0.0, -tunables.sprint_speed);
+        body.input.direction = Vec3::NEG_Z;
+        body.input.sprint = true;
+        let mut top_speed: f32 = 0.0;
+        for tick in 0..600 {
+            body.input.crouch = tick % 2 == 0;
+            body.step(&open());
+            top_speed = top_speed.max(body.speed());
+        }
+        assert!(
+            top_speed <= tunables.sprint_speed + tunables.slide_boost + 1e-4,
+            "{top_speed}"
+        );
+    }
+
+    #[test]
+    fn jumping_next_to_a_block_kicks_off_it() {
+        let tunables = MovementTunables::default();
+        let mut data = open();
+        data.set(5, 4, 2.0);
+        let collider = Collider::default();
+        let mut body = Body::at(Vec3::new(5.0 - collider.radius - 0.01, 0.5, 4.5));
+        body.physics.velocity = Vec3::new(1.0, -0.5, 0.0);
+        body.input.jump = true;
+        body.step(&data);
+        assert!(body.physics.velocity.x < 0.0);
+        assert_eq!(body.physics.velocity.y, tunables.wall_jump_speed);
+        assert!(!body.input.jump);
+
+        // Not again straight away
+        body.physics.velocity = Vec3::new(1.0, -0.5, 0.0);
+        body.input.jump = true;
+        body.step(&data);
+        assert_eq!(body.physics.velocity.y, -0.5);
+
+        // Nor in the open
+        let mut body = Body::at(Vec3::new(2.5, 0.5, 2.5));
+        body.physics.velocity.y = -0.5;
+        body.input.jump = true;
+        body.step(&data);
+        assert_eq!(body.physics.velocity.y, -0.5);
+    }
+}
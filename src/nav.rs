@@ -0,0 +1,157 @@
+//! Grid pathfinding over the arena. Cells holding a block are walls, everything else is open
+//! floor; paths move between the eight neighbours of a cell but never cut a wall's corner.
+
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use crate::scene::{SceneData, BLOCK_THRESHOLD};
+
+pub type Cell = (usize, usize);
+
+pub fn is_open(data: &SceneData, (x, z): Cell) -> bool {
+    data.get(x, z) <= BLOCK_THRESHOLD
+}
+
+/// Shortest path from `start` to `goal` with A*, including both ends, or `None` when `goal`
+/// can't be reached.
+pub fn find_path(data: &SceneData, start: Cell, goal: Cell) -> Option<Vec<Cell>> {
+    if !is_open(data, goal) {
+        return None;
+    }
+    let width = data.width();
+    let index = |(x, z): Cell| z * width + x;
+    let mut cost = vec![f32::INFINITY; width * data.depth()];
+    let mut came_from = vec![None; width * data.depth()];
+    let mut open = BinaryHeap::new();
+    cost[index(start)] = 0.0;
+    open.push(Node {
+        cell: start,
+        estimate: octile(start, goal),
+    });
+
+    while let Some(Node { cell, .. }) = open.pop() {
+        if cell == goal {
+            let mut path = vec![goal];
+            let mut current = goal;
+            while let Some(previous) = came_from[index(current)] {
+                path.push(previous);
+                current = previous;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for (neighbour, step) in neighbours(data, cell) {
+            let new_cost = cost[index(cell)] + step;
+            if new_cost < cost[index(neighbour)] {
+                cost[index(neighbour)] = new_cost;
+                came_from[index(neighbour)] = Some(cell);
+                open.push(Node {
+                    cell: neighbour,
+                    estimate: new_cost + octile(neighbour, goal),
+                });
+            }
+        }
+    }
+    None
+}
+
+fn neighbours(data: &SceneData, (x, z): Cell) -> impl Iterator<Item = (Cell, f32)> + '_ {
+    let open = move |dx: isize, dz: isize| {
+        data.get_checked(x as isize + dx, z as isize + dz)
+            .is_some_and(|height| height <= BLOCK_THRESHOLD)
+    };
+    (-1..=1)
+        .flat_map(|dz| (-1..=1).map(move |dx| (dx, dz)))
+        .filter(|&(dx, dz)| (dx, dz) != (0, 0))
+        .filter(move |&(dx, dz)| open(dx, dz))
+        // Diagonals need both sides open so bodies don't clip the corner
+        .filter(move |&(dx, dz)| dx == 0 || dz == 0 || (open(dx, 0) && open(0, dz)))
+        .map(move |(dx, dz)| {
+            let cell = ((x as isize + dx) as usize, (z as isize + dz) as usize);
+            let step = if dx != 0 && dz != 0 {
+                std::f32::consts::SQRT_2
+            } else {
+                1.0
+            };
+            (cell, step)
+        })
+}
+
+/// Distance on an eight connected grid with no obstacles.
+fn octile(a: Cell, b: Cell) -> f32 {
+    let dx = a.0.abs_diff(b.0) as f32;
+    let dz = a.1.abs_diff(b.1) as f32;
+    dx.max(dz) + (std::f32::consts::SQRT_2 - 1.0) * dx.min(dz)
+}
+
+struct Node {
+    cell: Cell,
+    estimate: f32,
+}
+
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.estimate == other.estimate
+    }
+}
+
+impl Eq for Node {}
+
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Node {
+    // Reversed so the heap pops the lowest estimate first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.total_cmp(&self.estimate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use super::*;
+
+    fn grid(rows: &[&str]) -> SceneData {
+        let mut data = SceneData::new(rows[0].len(), rows.len(), 1.0, Vec2::ZERO);
+        for (z, row) in rows.iter().enumerate() {
+            for (x, cell) in row.chars().enumerate() {
+                data.set(x, z, if cell == '#' { 1.0 } else { 0.0 });
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn straight_line_when_open() {
+        let data = grid(&[".....", ".....", "....."]);
+        let path = find_path(&data, (0, 1), (4, 1)).unwrap();
+        assert_eq!(path, vec![(0, 1), (1, 1), (2, 1), (3, 1), (4, 1)]);
+    }
+
+    #[test]
+    fn goes_around_walls() {
+        let data = grid(&[".....", ".###.", "....."]);
+        let path = find_path(&data, (0, 1), (4, 1)).unwrap();
+        assert!(path.iter().all(|cell| is_open(&data, *cell)));
+        assert_eq!(path.first(), Some(&(0, 1)));
+        assert_eq!(path.last(), Some(&(4, 1)));
+    }
+
+    #[test]
+    fn does_not_cut_corners() {
+        let data = grid(&["#.", ".."]);
+        let path = find_path(&data, (0, 1), (1, 0)).unwrap();
+        assert_eq!(path, vec![(0, 1), (1, 1), (1, 0)]);
+    }
+
+    #[test]
+    fn unreachable_goal() {
+        let data = grid(&["..#..", "..#..", "..#.."]);
+        assert_eq!(find_path(&data, (0, 0), (4, 2)), None);
+        assert_eq!(find_path(&data, (0, 0), (2, 1)), None);
+    }
+}
@@ -0,0 +1,190 @@
+//! Rockets and grenades. Projectiles are ordinary [`Physics`] bodies, so gravity and block
+//! collisions come from the same fixed step as the player; this module only decides when they
+//! blow up and pushes everything caught in the blast.
+
+use bevy::prelude::*;
+
+use crate::{
+    collision::Collider,
+    health::Damage,
+    main_material::MainMaterial,
+    map::Arena,
+    scene::SceneData,
+    sim::{physics, Physics},
+};
+
+/// How close a projectile has to pass to another body to set it off.
+const PROXIMITY_RADIUS: f32 = 0.3;
+
+pub struct ProjectilePlugin;
+impl Plugin for ProjectilePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ExplosionEvent>()
+            .add_startup_system(setup.in_base_set(StartupSet::PostStartup))
+            .add_systems(
+                (update_projectiles, apply_explosions)
+                    .chain()
+                    .after(physics)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect, FromReflect)]
+pub enum ProjectileKind {
+    /// Fast, flies nearly straight and explodes on contact.
+    Rocket,
+    /// Lobbed, bounces and explodes when its fuse runs out.
+    Grenade,
+}
+
+#[derive(Debug, Component, Reflect)]
+pub struct Projectile {
+    pub owner: Entity,
+    pub kind: ProjectileKind,
+    /// Seconds until it explodes on its own.
+    pub fuse: f32,
+    pub blast_radius: f32,
+    /// Speed given to bodies at the center of the blast, falling off to nothing at its edge.
+    pub knockback: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ExplosionEvent {
+    pub owner: Entity,
+    pub position: Vec3,
+    pub radius: f32,
+    /// Damage at the center of the blast, falling off like the knockback.
+    pub damage: f32,
+    pub knockback: f32,
+}
+
+impl ExplosionEvent {
+    /// How much of the blast reaches `point`, 1 at the center down to 0 at `radius`.
+    pub fn falloff(&self, point: Vec3) -> f32 {
+        (1.0 - point.distance(self.position) / self.radius).max(0.0)
+    }
+}
+
+#[derive(Resource)]
+pub struct ProjectileAssets {
+    mesh: Handle<Mesh>,
+    material: Handle<MainMaterial>,
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<MainMaterial>>,
+    arena: Res<Arena>,
+    data: Res<SceneData>,
+) {
+    commands.insert_resource(ProjectileAssets {
+        mesh: meshes.add(
+            shape::UVSphere {
+                radius: 0.05,
+                ..default()
+            }
+            .into(),
+        ),
+        material: materials.add(MainMaterial {
+            color: Color::rgb(1.0, 0.8, 0.3),
+            boxes: Some(arena.box_texture.clone()),
+            map: data.shader_params(),
+        }),
+    });
+}
+
+/// Launch a projectile from `origin` along `direction`.
+pub fn spawn_projectile(
+    commands: &mut Commands,
+    assets: &ProjectileAssets,
+    owner: Entity,
+    kind: ProjectileKind,
+    origin: Vec3,
+    direction: Vec3,
+) {
+    let (speed, restitution, fuse) = match kind {
+        ProjectileKind::Rocket => (20.0, 0.0, 5.0),
+        ProjectileKind::Grenade => (8.0, 0.5, 2.0),
+    };
+    commands.spawn((
+        Physics {
+            velocity: direction * speed,
+            restitution,
+            ..Physics::at(origin)
+        },
+        Collider {
+            radius: 0.05,
+            height: 0.1,
+            step_height: 0.0,
+        },
+        Projectile {
+            owner,
+            kind,
+            fuse,
+            blast_radius: 1.5,
+            knockback: 8.0,
+        },
+        Damage(50.0),
+        MaterialMeshBundle {
+            mesh: assets.mesh.clone(),
+            material: assets.material.clone(),
+            transform: Transform::from_translation(origin),
+            ..default()
+        },
+    ));
+}
+
+fn update_projectiles(
+    mut commands: Commands,
+    fixed_time: Res<FixedTime>,
+    mut projectiles: Query<(Entity, &mut Projectile, &Damage, &Physics)>,
+    bodies: Query<(Entity, &Physics, &Collider), Without<Projectile>>,
+    mut explosions: EventWriter<ExplosionEvent>,
+) {
+    let delta = fixed_time.period.as_secs_f32();
+    for (entity, mut projectile, damage, physics) in projectiles.iter_mut() {
+        projectile.fuse -= delta;
+        let touched_body = bodies.iter().any(|(body, other, collider)| {
+            body != projectile.owner
+                && center(other, collider).distance(physics.position) < PROXIMITY_RADIUS
+        });
+        let contact = physics.contact && projectile.kind == ProjectileKind::Rocket;
+        if projectile.fuse <= 0.0 || contact || touched_body {
+            explosions.send(ExplosionEvent {
+                owner: projectile.owner,
+                position: physics.position,
+                radius: projectile.blast_radius,
+                damage: damage.0,
+                knockback: projectile.knockback,
+            });
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+/// Push every body in range away from the blast, the shooter included so rocket jumps work.
+fn apply_explosions(
+    mut explosions: EventReader<ExplosionEvent>,
+    mut bodies: Query<(&mut Physics, &Collider), Without<Projectile>>,
+) {
+    for explosion in explosions.iter() {
+        for (mut physics, collider) in bodies.iter_mut() {
+            let offset = center(&physics, collider) - explosion.position;
+            let falloff = explosion.falloff(center(&physics, collider));
+            if falloff <= 0.0 {
+                continue;
+            }
+            // Bias upwards so blasts under the feet launch rather than slide
+            let direction = (offset.normalize_or_zero() + Vec3::Y * 0.5).normalize();
+            physics.velocity += direction * explosion.knockback * falloff;
+            physics.on_ground = false;
+        }
+    }
+}
+
+/// Middle of a body's collider, where blasts are measured to.
+pub fn center(physics: &Physics, collider: &Collider) -> Vec3 {
+    physics.position + Vec3::Y * collider.height * 0.5
+}
@@ -0,0 +1,243 @@
+//! Critters that die fall apart instead of vanishing. Their leg joints become verlet
+//! particles held together by the leg segment lengths and a few struts across the body, and
+//! the corpse is drawn with the same mesh builder as a living critter.
+
+use std::ops::Range;
+
+use bevy::{math::Vec3Swizzles, prelude::*};
+
+use crate::{
+    critter::{
+        make_hlod_critter_mesh, make_llod_critter_mesh, write_critter_mesh, Critter, CritterSpec,
+        MeshDetail,
+    },
+    health::{DamageSet, DeathEvent, Respawns},
+    main_material::MainMaterial,
+    scene::SceneData,
+};
+
+/// Constraint passes per step, more keeps the legs from stretching when they land.
+const ITERATIONS: usize = 8;
+/// Fraction of velocity kept each step.
+const DRAG: f32 = 0.99;
+/// Fraction of sideways motion lost by particles touching the ground.
+const FRICTION: f32 = 0.3;
+/// Seconds a corpse stays around.
+const LIFETIME: f32 = 10.0;
+
+pub struct RagdollPlugin;
+impl Plugin for RagdollPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(spawn_ragdolls.after(DamageSet))
+            .add_system(simulate_ragdolls.in_schedule(CoreSchedule::FixedUpdate))
+            .add_system(update_ragdoll_meshes);
+    }
+}
+
+#[derive(Debug, Component)]
+pub struct Ragdoll {
+    particles: Vec<Vec3>,
+    previous: Vec<Vec3>,
+    /// Pairs of particles and the distance kept between them.
+    constraints: Vec<(usize, usize, f32)>,
+    /// Each leg's particles, from the body out to the foot.
+    legs: Vec<Range<usize>>,
+    /// The particle at the center of the body, the rest hang off it.
+    body: usize,
+    spec: CritterSpec,
+    age: f32,
+}
+
+impl Ragdoll {
+    fn from_critter(critter: &Critter, transform: &GlobalTransform, delta: f32) -> Self {
+        let body = transform.translation();
+        let mut particles = vec![body];
+        let mut constraints = Vec::new();
+        let mut legs = Vec::new();
+        for joints in critter.leg_joints() {
+            let start = particles.len();
+            particles.extend_from_slice(joints);
+            constraints.push((0, start, body.distance(joints[0])));
+            for (i, length) in critter.spec.segments.iter().enumerate() {
+                constraints.push((start + i, start + i + 1, *length));
+            }
+            legs.push(start..particles.len());
+        }
+        // Struts between the roots of the legs keep the body from folding up
+        let roots = legs.iter().map(|leg| leg.start).collect::<Vec<_>>();
+        for (i, a) in roots.iter().enumerate() {
+            for b in [
+                roots[(i + 1) % roots.len()],
+                roots[(i + roots.len() / 2) % roots.len()],
+            ] {
+                if *a != b {
+                    constraints.push((*a, b, particles[*a].distance(particles[b])));
+                }
+            }
+        }
+        let previous = particles
+            .iter()
+            .map(|particle| *particle - critter.velocity * delta)
+            .collect();
+        Self {
+            particles,
+            previous,
+            constraints,
+            legs,
+            body: 0,
+            spec: critter.spec.clone(),
+            age: 0.0,
+        }
+    }
+
+    fn step(&mut self, data: &SceneData, delta: f32) {
+        let gravity = Vec3::NEG_Y * 9.81 * delta * delta;
+        for (particle, previous) in self.particles.iter_mut().zip(self.previous.iter_mut()) {
+            let velocity = (*particle - *previous) * DRAG;
+            *previous = *particle;
+            *particle += velocity + gravity;
+        }
+        for _ in 0..ITERATIONS {
+            for (a, b, length) in self.constraints.iter().copied() {
+                let offset = self.particles[b] - self.particles[a];
+                let distance = offset.length();
+                if distance <= f32::EPSILON {
+                    continue;
+                }
+                let correction = offset * (0.5 * (distance - length) / distance);
+                self.particles[a] += correction;
+                self.particles[b] -= correction;
+            }
+            for (particle, previous) in self.particles.iter_mut().zip(self.previous.iter()) {
+                collide(data, particle, *previous);
+            }
+        }
+    }
+
+    fn leg_joints(&self) -> impl Iterator<Item = &[Vec3]> + '_ {
+        self.legs.iter().map(|leg| &self.particles[leg.clone()])
+    }
+
+    /// Position and orientation of the body, from where the legs are attached to it.
+    fn transform(&self) -> Transform {
+        let mean = |f: &dyn Fn(usize) -> bool| {
+            let roots = self
+                .legs
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| f(*i))
+                .map(|(_, leg)| self.particles[leg.start])
+                .collect::<Vec<_>>();
+            roots.iter().sum::<Vec3>() / roots.len().max(1) as f32
+        };
+        // Legs go up the right side from the back then down the left, see `make_cirtter`
+        let (count, half) = (self.legs.len(), self.legs.len() / 2);
+        let right = mean(&|i| i < half) - mean(&|i| i >= half);
+        let back = mean(&|i| i < half / 2 || i >= count - half / 2)
+            - mean(&|i| i >= half / 2 && i < count - half / 2);
+        let x = right.try_normalize().unwrap_or(Vec3::X);
+        let y = back.cross(x).try_normalize().unwrap_or(Vec3::Y);
+        let z = x.cross(y);
+        Transform::from_translation(self.particles[self.body])
+            .with_rotation(Quat::from_mat3(&Mat3::from_cols(x, y, z)))
+    }
+}
+
+/// Keep a particle out of the floor and blocks. Particles that come down onto something rest
+/// on top of it, ones that run into the side of a block are stopped where they were.
+fn collide(data: &SceneData, particle: &mut Vec3, previous: Vec3) {
+    let ground = data.ground_height(particle.xz());
+    if particle.y >= ground {
+        return;
+    }
+    if previous.y >= ground - 1e-3 {
+        let slide = particle.xz().lerp(previous.xz(), FRICTION);
+        *particle = Vec3::new(slide.x, ground, slide.y);
+    } else {
+        let ground = data.ground_height(previous.xz());
+        *particle = Vec3::new(previous.x, particle.y.max(ground), previous.z);
+    }
+}
+
+fn spawn_ragdolls(
+    mut commands: Commands,
+    mut deaths: EventReader<DeathEvent>,
+    fixed_time: Res<FixedTime>,
+    respawns: Query<(), With<Respawns>>,
+    children: Query<&Children>,
+    critters: Query<(
+        &Critter,
+        &GlobalTransform,
+        &Handle<MainMaterial>,
+        &MeshDetail,
+    )>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let delta = fixed_time.period.as_secs_f32();
+    for death in deaths.iter() {
+        if respawns.contains(death.entity) {
+            continue;
+        }
+        let bodies = std::iter::once(death.entity)
+            .chain(children.iter_descendants(death.entity))
+            .filter_map(|entity| critters.get(entity).ok());
+        for (critter, transform, material, detail) in bodies {
+            let ragdoll = Ragdoll::from_critter(critter, transform, delta);
+            let transform = ragdoll.transform();
+            let mesh = make_ragdoll_mesh(&ragdoll, transform, *detail);
+            commands.spawn((
+                *detail,
+                MaterialMeshBundle {
+                    mesh: meshes.add(mesh),
+                    material: material.clone(),
+                    transform,
+                    ..default()
+                },
+                ragdoll,
+            ));
+        }
+    }
+}
+
+fn simulate_ragdolls(
+    mut commands: Commands,
+    fixed_time: Res<FixedTime>,
+    data: Res<SceneData>,
+    mut ragdolls: Query<(Entity, &mut Ragdoll)>,
+) {
+    let delta = fixed_time.period.as_secs_f32();
+    for (entity, mut ragdoll) in ragdolls.iter_mut() {
+        ragdoll.age += delta;
+        if ragdoll.age > LIFETIME {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+        ragdoll.step(&data, delta);
+    }
+}
+
+fn update_ragdoll_meshes(
+    mut ragdolls: Query<(&Ragdoll, &mut Transform, &Handle<Mesh>, &MeshDetail)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    for (ragdoll, mut transform, mesh, detail) in ragdolls.iter_mut() {
+        *transform = ragdoll.transform();
+        if let Some(mesh) = meshes.get_mut(mesh) {
+            write_critter_mesh(
+                mesh,
+                ragdoll.leg_joints(),
+                (*transform).into(),
+                &ragdoll.spec,
+                *detail,
+            );
+        }
+    }
+}
+
+fn make_ragdoll_mesh(ragdoll: &Ragdoll, transform: Transform, detail: MeshDetail) -> Mesh {
+    let (legs, transform) = (ragdoll.leg_joints(), transform.into());
+    match detail {
+        MeshDetail::Low => make_llod_critter_mesh(legs, transform, &ragdoll.spec),
+        MeshDetail::High => make_hlod_critter_mesh(legs, transform, &ragdoll.spec),
+    }
+}
@@ -0,0 +1,148 @@
+//! The arena heightfield: a runtime sized grid of block heights.
+
+use bevy::{
+    math::{vec2, vec4},
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+
+/// Cells with a height above this are solid blocks, anything below is open floor.
+pub const BLOCK_THRESHOLD: f32 = 0.6;
+
+/*
+  origin
+    *----------> +x (width)
+    | 0,0 | 1,0 |
+    |-----+-----|
+    | 0,1 | 1,1 |
+    v
+   +z (depth)
+  Each cell is `cell_size` wide, heights are stored row by row (z major).
+*/
+#[derive(Resource, Debug, Clone, Reflect)]
+pub struct SceneData {
+    width: usize,
+    depth: usize,
+    cell_size: f32,
+    origin: Vec2,
+    heights: Vec<f32>,
+}
+
+impl SceneData {
+    /// A flat map with its minimum corner at `origin`.
+    pub fn new(width: usize, depth: usize, cell_size: f32, origin: Vec2) -> Self {
+        Self {
+            width,
+            depth,
+            cell_size,
+            origin,
+            heights: vec![0.0; width * depth],
+        }
+    }
+
+    /// A flat map centered on the world origin.
+    pub fn centered(width: usize, depth: usize, cell_size: f32) -> Self {
+        let origin = vec2(width as f32, depth as f32) * cell_size * -0.5;
+        Self::new(width, depth, cell_size, origin)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn origin(&self) -> Vec2 {
+        self.origin
+    }
+
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    pub fn get(&self, x: usize, z: usize) -> f32 {
+        self.heights[self.index(x, z)]
+    }
+
+    /// Like [`SceneData::get`] but returns `None` for cells outside the map.
+    pub fn get_checked(&self, x: isize, z: isize) -> Option<f32> {
+        if x < 0 || z < 0 || x as usize >= self.width || z as usize >= self.depth {
+            return None;
+        }
+        Some(self.get(x as usize, z as usize))
+    }
+
+    /// Height of the block in a cell, or `None` if the cell is open or off the map.
+    pub fn block_height(&self, x: isize, z: isize) -> Option<f32> {
+        self.get_checked(x, z).filter(|h| *h > BLOCK_THRESHOLD)
+    }
+
+    pub fn set(&mut self, x: usize, z: usize, height: f32) {
+        let i = self.index(x, z);
+        self.heights[i] = height;
+    }
+
+    /// Iterate over every cell coordinate in the map.
+    pub fn cells(&self) -> impl Iterator<Item = (usize, usize)> {
+        let width = self.width;
+        (0..self.depth).flat_map(move |z| (0..width).map(move |x| (x, z)))
+    }
+
+    /// Continuous grid coordinates of a world space xz position.
+    pub fn world_to_grid(&self, pos: Vec2) -> Vec2 {
+        (pos - self.origin) / self.cell_size
+    }
+
+    /// World space xz position of continuous grid coordinates.
+    pub fn grid_to_world(&self, grid: Vec2) -> Vec2 {
+        grid * self.cell_size + self.origin
+    }
+
+    /// The cell containing a world space xz position, if it is inside the map.
+    pub fn cell_at(&self, pos: Vec2) -> Option<(usize, usize)> {
+        let grid = self.world_to_grid(pos).floor();
+        let (x, z) = (grid.x as isize, grid.y as isize);
+        self.get_checked(x, z).map(|_| (x as usize, z as usize))
+    }
+
+    /// Height of whatever is standing at a world space xz position, the top of a block or the
+    /// floor.
+    pub fn ground_height(&self, pos: Vec2) -> f32 {
+        self.cell_at(pos)
+            .and_then(|(x, z)| self.block_height(x as isize, z as isize))
+            .unwrap_or(0.0)
+    }
+
+    pub fn cell_center(&self, x: usize, z: usize) -> Vec2 {
+        self.grid_to_world(vec2(x as f32 + 0.5, z as f32 + 0.5))
+    }
+
+    /// Upload the heights as a single channel float texture, one texel per cell.
+    pub fn to_image(&self) -> Image {
+        Image::new(
+            Extent3d {
+                width: self.width as u32,
+                height: self.depth as u32,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            self.heights.iter().flat_map(|h| h.to_ne_bytes()).collect(),
+            TextureFormat::R32Float,
+        )
+    }
+
+    /// Layout parameters for `main_material.wgsl`: origin in xy, cell size in z.
+    pub fn shader_params(&self) -> Vec4 {
+        vec4(self.origin.x, self.origin.y, self.cell_size, 0.0)
+    }
+
+    fn index(&self, x: usize, z: usize) -> usize {
+        assert!(
+            x < self.width && z < self.depth,
+            "cell {x},{z} is off the map"
+        );
+        z * self.width + x
+    }
+}
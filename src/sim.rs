@@ -0,0 +1,231 @@
+//! The render independent part of the game: player movement, fixed step physics against the
+//! arena and critter legs following their bodies. [`GameSimPlugin`] only needs `MinimalPlugins`,
+//! `TransformPlugin` and a [`SceneData`], so the simulation can be stepped on a machine without
+//! a GPU, see [`run_ticks`]. The player moves by the [`ActionState`], set it directly or add
+//! bevy's `InputPlugin` and [`crate::actions::ActionsPlugin`] to drive it from devices.
+//! Nothing moves until the [`GameState`] is `Playing`.
+
+use std::f32::consts::FRAC_PI_2;
+
+use anyhow::Result;
+use bevy::{prelude::*, time::TimeUpdateStrategy, utils::Instant};
+
+use crate::{
+    actions::{Action, ActionState},
+    collision::{move_and_collide, Collider},
+    critter::{coordinate_critter, update_critter, Critter, CritterRng},
+    movement::{Movement, MovementPlugin},
+    scene::SceneData,
+    state::GameState,
+};
+
+/// How far the view can pitch up or down, in radians.
+pub const VIEW_LOCK: f32 = FRAC_PI_2;
+
+/// Length of one simulation step, physics runs at a fixed rate regardless of frame rate.
+pub const TICK_SECONDS: f32 = 1.0 / 60.0;
+
+pub struct GameSimPlugin;
+impl Plugin for GameSimPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_state::<GameState>()
+            .insert_resource(FixedTime::new_from_secs(TICK_SECONDS))
+            .init_resource::<CritterRng>()
+            .init_resource::<ActionState>()
+            .add_plugin(MovementPlugin)
+            .add_systems(
+                (physics, update_critter_velocity)
+                    .chain()
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            )
+            .add_system(pause_time.in_schedule(OnEnter(GameState::MainMenu)))
+            .add_system(pause_time.in_schedule(OnEnter(GameState::Paused)))
+            .add_system(unpause_time.in_schedule(OnEnter(GameState::Playing)))
+            .add_system(
+                player_move_input
+                    .run_if(in_state(GameState::Playing))
+                    .run_if(resource_exists::<MainPlayer>()),
+            )
+            .add_system(interpolate_transforms)
+            .add_systems(
+                (coordinate_critter, update_critter)
+                    .chain()
+                    .in_set(CritterLegSet),
+            );
+    }
+}
+
+/// Critter legs picking their steps and moving along them, anything placed relative to the
+/// feet runs after this.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CritterLegSet;
+
+/// Time stands still outside of play, fixed steps stop along with everything else timed.
+fn pause_time(mut time: ResMut<Time>) {
+    time.pause();
+}
+
+fn unpause_time(mut time: ResMut<Time>) {
+    time.unpause();
+}
+
+/// Step `app` by exactly `ticks` fixed steps, however long each update really takes. The
+/// first update of a fresh app only starts the clock, so it is run before counting.
+pub fn run_ticks(app: &mut App, ticks: usize) {
+    if app.world.resource::<Time>().last_update().is_none() {
+        app.insert_resource(TimeUpdateStrategy::ManualInstant(Instant::now()));
+        app.update();
+    }
+    let period = app.world.resource::<FixedTime>().period;
+    for _ in 0..ticks {
+        let last = app.world.resource::<Time>().last_update().unwrap();
+        app.insert_resource(TimeUpdateStrategy::ManualInstant(last + period));
+        app.update();
+    }
+}
+
+#[derive(Reflect, Debug, Default, Component)]
+pub struct Physics {
+    pub velocity: Vec3,
+    pub on_ground: bool,
+    /// Position after the latest fixed step, `Transform::translation` trails it by up to one
+    /// step so it can be interpolated from `previous_position`.
+    pub position: Vec3,
+    pub previous_position: Vec3,
+    /// Fraction of the velocity kept, reversed, when hitting a surface.
+    pub restitution: f32,
+    /// The last step was cut short by the floor or a block.
+    pub contact: bool,
+}
+
+impl Physics {
+    pub fn at(position: Vec3) -> Self {
+        Self {
+            position,
+            previous_position: position,
+            ..default()
+        }
+    }
+
+    /// Move without interpolating through the space in between.
+    pub fn teleport(&mut self, position: Vec3) {
+        self.position = position;
+        self.previous_position = position;
+    }
+}
+
+/// Movement requested by the player since the last fixed step.
+#[derive(Reflect, Debug, Default, Component)]
+pub struct MoveInput {
+    /// World space direction to move in, at full speed when its length is one.
+    pub direction: Vec3,
+    pub sprint: bool,
+    pub crouch: bool,
+    /// Held until a fixed step consumes it, so a short press can't fall between steps.
+    pub jump: bool,
+}
+
+#[derive(Resource, Reflect, Debug)]
+pub struct MainPlayer {
+    pub id: Entity,
+    pub gimble_id: Entity,
+    pub camera_id: Entity,
+}
+
+#[derive(Reflect, Debug, Default, Component)]
+pub struct Gimble {
+    pub theta: f32,
+}
+
+fn update_critter_velocity(
+    physics_havers: Query<(&Children, &Physics)>,
+    mut critters: Query<&mut Critter>,
+    fixed_time: Res<FixedTime>,
+) {
+    let delta = fixed_time.period.as_secs_f32();
+    for (children, physics) in physics_havers.iter() {
+        for child in children {
+            let _ = critters
+                .get_mut(*child)
+                .map(|mut crit| crit.step_velocity(physics.velocity, delta));
+        }
+    }
+}
+
+pub fn physics(
+    mut bodies: Query<(&mut Physics, &Collider, Option<&Movement>)>,
+    fixed_time: Res<FixedTime>,
+    data: Res<SceneData>,
+) {
+    let delta = fixed_time.period.as_secs_f32();
+    for (mut physics, collider, movement) in bodies.iter_mut() {
+        physics.previous_position = physics.position;
+        physics.velocity.y -= delta * 9.81;
+        do_scene_colisions(&mut physics, collider, &data, delta);
+        // Bodies with a `Movement` get their friction from it
+        if physics.on_ground && movement.is_none() {
+            physics.velocity.x *= 0.7;
+            physics.velocity.z *= 0.7;
+        }
+    }
+}
+
+pub fn do_scene_colisions(
+    physics: &mut Physics,
+    collider: &Collider,
+    data: &SceneData,
+    delta: f32,
+) {
+    let sweep = move_and_collide(data, collider, physics.position, physics.velocity * delta);
+    physics.position = sweep.position;
+    let bounce = -physics.restitution;
+    if sweep.blocked.x {
+        physics.velocity.x *= bounce;
+    }
+    if sweep.blocked.y {
+        physics.velocity.y *= bounce;
+    }
+    if sweep.blocked.z {
+        physics.velocity.z *= bounce;
+    }
+    physics.on_ground = sweep.on_ground;
+    physics.contact = sweep.blocked.any();
+}
+
+/// Place rendered bodies between their last two fixed steps.
+fn interpolate_transforms(
+    mut bodies: Query<(&mut Transform, &Physics)>,
+    fixed_time: Res<FixedTime>,
+) {
+    let alpha = fixed_time.accumulated().as_secs_f32() / fixed_time.period.as_secs_f32();
+    for (mut transform, physics) in bodies.iter_mut() {
+        transform.translation = physics
+            .previous_position
+            .lerp(physics.position, alpha.min(1.0));
+    }
+}
+
+fn player_move_input(
+    actions: Res<ActionState>,
+    main_player: Res<MainPlayer>,
+    mut player: Query<(&mut MoveInput, &Transform)>,
+) {
+    let _ = || -> Result<()> {
+        let (mut input, transform) = player.get_mut(main_player.id)?;
+        read_move_actions(&actions, transform, &mut input);
+        Ok(())
+    }();
+}
+
+/// Turn the movement actions into a move relative to where `transform` faces.
+pub fn read_move_actions(actions: &ActionState, transform: &Transform, input: &mut MoveInput) {
+    let forward = actions.value(Action::MoveForward);
+    let strafe = actions.value(Action::Strafe);
+    input.direction = transform.forward() * forward + transform.right() * strafe;
+    input.sprint = actions.pressed(Action::Sprint);
+    input.crouch = actions.pressed(Action::Crouch);
+
+    if actions.just_pressed(Action::Jump) {
+        input.jump = true;
+    }
+}
@@ -0,0 +1,95 @@
+//! Whether the game is being played. It starts in the menu, [`Action::Confirm`] starts playing,
+//! and [`Action::Pause`] or the window losing focus pauses. The cursor is captured only while playing, and outside
+//! of it player input is ignored and `Time` is paused, which freezes the fixed step simulation
+//! and everything else driven by time, see [`crate::sim::GameSimPlugin`].
+
+use bevy::{
+    prelude::*,
+    window::{CursorGrabMode, PrimaryWindow, WindowFocused},
+};
+
+use crate::actions::{Action, ActionState};
+
+const TITLE: &str = "Shooter";
+
+#[derive(States, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum GameState {
+    #[default]
+    MainMenu,
+    Playing,
+    Paused,
+}
+
+/// Menu input, the cursor and the window title. The state itself is added by
+/// [`crate::sim::GameSimPlugin`] so it also exists without a window.
+pub struct GameStatePlugin;
+impl Plugin for GameStatePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(grab_cursor.in_schedule(OnEnter(GameState::Playing)))
+            .add_system(release_cursor.in_schedule(OnExit(GameState::Playing)))
+            .add_system(menu_input)
+            .add_system(pause_on_focus_loss.run_if(in_state(GameState::Playing)))
+            .add_system(show_state);
+    }
+}
+
+/*
+    MainMenu --confirm--> Playing <--confirm/pause-- Paused
+                             |                         ^
+                             +----pause/focus lost-----+
+*/
+fn menu_input(
+    state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    actions: Res<ActionState>,
+) {
+    let confirm = actions.just_pressed(Action::Confirm);
+    let pause = actions.just_pressed(Action::Pause);
+    match state.0 {
+        GameState::MainMenu if confirm => next_state.set(GameState::Playing),
+        GameState::Playing if pause => next_state.set(GameState::Paused),
+        GameState::Paused if confirm || pause => next_state.set(GameState::Playing),
+        _ => {}
+    }
+}
+
+fn pause_on_focus_loss(
+    mut focus: EventReader<WindowFocused>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if focus.iter().any(|event| !event.focused) {
+        next_state.set(GameState::Paused);
+    }
+}
+
+fn grab_cursor(mut windows: Query<&mut Window, With<PrimaryWindow>>) {
+    let Ok(mut window) = windows.get_single_mut() else {
+        return;
+    };
+    window.cursor.grab_mode = CursorGrabMode::Locked;
+    window.cursor.visible = false;
+}
+
+fn release_cursor(mut windows: Query<&mut Window, With<PrimaryWindow>>) {
+    let Ok(mut window) = windows.get_single_mut() else {
+        return;
+    };
+    window.cursor.grab_mode = CursorGrabMode::None;
+    window.cursor.visible = true;
+}
+
+/// There's no menu to draw yet, so say what to do in the title bar.
+fn show_state(state: Res<State<GameState>>, mut windows: Query<&mut Window, With<PrimaryWindow>>) {
+    if !state.is_changed() {
+        return;
+    }
+    let Ok(mut window) = windows.get_single_mut() else {
+        return;
+    };
+    window.title = match state.0 {
+        GameState::MainMenu => format!("{TITLE} - click or press enter to play"),
+        GameState::Playing => TITLE.to_string(),
+        GameState::Paused => format!("{TITLE} - paused, click or press enter to resume"),
+    };
+    info!("entered {:?}", state.0);
+}
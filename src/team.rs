@@ -0,0 +1,98 @@
+//! The two sides of the arena. Each [`Team`] owns one half of the floor, see
+//! [`crate::map::TeamHalf`], spawns in it and by default can't hurt its own members, see
+//! [`FriendlyFire`]. The player picks a side with `--team red` or `--team blue` and critters
+//! are sent in for the other one.
+
+use anyhow::{bail, Result};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Component, Reflect, FromReflect,
+)]
+pub enum Team {
+    Blue,
+    Red,
+}
+
+impl Team {
+    pub fn opponent(self) -> Self {
+        match self {
+            Team::Blue => Team::Red,
+            Team::Red => Team::Blue,
+        }
+    }
+
+    /// The team asked for with `--team`, if any.
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Option<Self>> {
+        let mut team = None;
+        let mut args = args.skip(1);
+        while let Some(arg) = args.next() {
+            if arg != "--team" {
+                continue;
+            }
+            team = match args.next().as_deref() {
+                Some("blue") => Some(Team::Blue),
+                Some("red") => Some(Team::Red),
+                other => bail!("unknown team {other:?}, expected blue or red"),
+            };
+        }
+        Ok(team)
+    }
+}
+
+/// Share of their damage teammates deal each other, none unless changed.
+#[derive(Resource, Reflect, Debug, Clone, Copy, Default)]
+#[reflect(Resource)]
+pub struct FriendlyFire(pub f32);
+
+impl FriendlyFire {
+    /// Multiplier for damage dealt by a body on `source` to one on `target`. Bodies without a
+    /// team hurt and are hurt by everyone.
+    pub fn scale(&self, source: Option<Team>, target: Option<Team>) -> f32 {
+        match (source, target) {
+            (Some(source), Some(target)) if source == target => self.0,
+            _ => 1.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        ["shooter"]
+            .iter()
+            .chain(args)
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn teams_are_picked_from_the_command_line() {
+        let team = |list: &[&str]| Team::from_args(args(list)).ok();
+        assert_eq!(team(&["--team", "red"]), Some(Some(Team::Red)));
+        assert_eq!(
+            team(&["--seed", "3", "--team", "blue"]),
+            Some(Some(Team::Blue))
+        );
+        assert_eq!(team(&[]), Some(None));
+        assert_eq!(team(&["--team", "green"]), None);
+        assert_eq!(team(&["--team"]), None);
+    }
+
+    #[test]
+    fn teammates_are_spared_by_default() {
+        let rule = FriendlyFire::default();
+        assert_eq!(rule.scale(Some(Team::Red), Some(Team::Red)), 0.0);
+        assert_eq!(rule.scale(Some(Team::Red), Some(Team::Blue)), 1.0);
+        assert_eq!(rule.scale(None, Some(Team::Blue)), 1.0);
+        assert_eq!(rule.scale(Some(Team::Blue), None), 1.0);
+        assert_eq!(
+            FriendlyFire(0.5).scale(Some(Team::Blue), Some(Team::Blue)),
+            0.5
+        );
+    }
+}
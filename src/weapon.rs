@@ -0,0 +1,252 @@
+//! Player weapons. Hitscan shots are rays from the camera tested against the arena blocks, the
+//! floor and critter legs; whatever is hit nearest is reported as a [`HitEvent`]. Launchers
+//! hand off to [`crate::projectile`] instead.
+
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use rand::{thread_rng, Rng};
+
+use crate::{
+    actions::{Action, ActionState},
+    collision::{ray_capsule, ray_sphere, raycast},
+    critter::Critter,
+    projectile::{spawn_projectile, ProjectileAssets, ProjectileKind},
+    scene::SceneData,
+    sim::{Gimble, MainPlayer, VIEW_LOCK},
+    state::GameState,
+};
+
+/// Radius of the sphere around a critter's body that counts as a hit.
+const CRITTER_BODY_RADIUS: f32 = 0.1;
+/// Legs are thinner than this but need to be hittable.
+const CRITTER_LEG_RADIUS: f32 = 0.04;
+
+pub struct WeaponPlugin;
+impl Plugin for WeaponPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<HitEvent>()
+            .init_resource::<TriggerLock>()
+            .add_system(lock_trigger.in_schedule(OnEnter(GameState::Playing)))
+            .add_system(switch_weapons.run_if(in_state(GameState::Playing)))
+            .add_system(
+                fire_weapons
+                    .after(switch_weapons)
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+/// Set on entering play so the click that started or resumed the game doesn't also fire,
+/// cleared once fire is let go.
+#[derive(Resource, Default)]
+struct TriggerLock(bool);
+
+fn lock_trigger(mut lock: ResMut<TriggerLock>) {
+    lock.0 = true;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect)]
+pub enum WeaponKind {
+    Hitscan,
+    Launcher(ProjectileKind),
+}
+
+#[derive(Debug, Component, Reflect)]
+pub struct Weapon {
+    pub kind: WeaponKind,
+    /// Shots per second while the trigger is held.
+    pub fire_rate: f32,
+    /// Half angle of the cone shots scatter in, in radians.
+    pub spread: f32,
+    /// How far each shot kicks the view up, in radians.
+    pub recoil: f32,
+    pub damage: f32,
+    pub range: f32,
+    cooldown: f32,
+}
+
+impl Weapon {
+    pub fn rifle() -> Self {
+        Self {
+            kind: WeaponKind::Hitscan,
+            fire_rate: 8.0,
+            spread: 0.01,
+            recoil: 0.02,
+            damage: 10.0,
+            range: 100.0,
+            cooldown: 0.0,
+        }
+    }
+
+    pub fn rocket_launcher() -> Self {
+        Self {
+            kind: WeaponKind::Launcher(ProjectileKind::Rocket),
+            fire_rate: 1.25,
+            spread: 0.0,
+            recoil: 0.08,
+            ..Self::rifle()
+        }
+    }
+
+    pub fn grenade_launcher() -> Self {
+        Self {
+            kind: WeaponKind::Launcher(ProjectileKind::Grenade),
+            fire_rate: 1.5,
+            spread: 0.02,
+            recoil: 0.05,
+            ..Self::rifle()
+        }
+    }
+}
+
+impl Default for Weapon {
+    fn default() -> Self {
+        Self::rifle()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HitTarget {
+    Floor,
+    Block { x: usize, z: usize },
+    Critter(Entity),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HitEvent {
+    pub shooter: Entity,
+    pub target: HitTarget,
+    pub point: Vec3,
+    /// Direction the shot was travelling.
+    pub direction: Vec3,
+    pub damage: f32,
+}
+
+/// Every weapon the player carries, in the order [`Action::NextWeapon`] steps through them.
+pub const LOADOUT: [fn() -> Weapon; 3] = [
+    Weapon::rifle,
+    Weapon::rocket_launcher,
+    Weapon::grenade_launcher,
+];
+
+fn switch_weapons(
+    actions: Res<ActionState>,
+    player: Res<MainPlayer>,
+    mut weapons: Query<&mut Weapon>,
+) {
+    let Ok(mut weapon) = weapons.get_mut(player.camera_id) else {
+        return;
+    };
+    let current = LOADOUT
+        .iter()
+        .position(|make| make().kind == weapon.kind)
+        .unwrap_or(0);
+    let selected = if actions.just_pressed(Action::SelectRifle) {
+        0
+    } else if actions.just_pressed(Action::SelectRocketLauncher) {
+        1
+    } else if actions.just_pressed(Action::SelectGrenadeLauncher) {
+        2
+    } else if actions.just_pressed(Action::NextWeapon) {
+        (current + 1) % LOADOUT.len()
+    } else if actions.just_pressed(Action::PreviousWeapon) {
+        (current + LOADOUT.len() - 1) % LOADOUT.len()
+    } else {
+        return;
+    };
+    if selected != current {
+        *weapon = LOADOUT[selected]();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn fire_weapons(
+    mut commands: Commands,
+    actions: Res<ActionState>,
+    mut lock: ResMut<TriggerLock>,
+    time: Res<Time>,
+    player: Res<MainPlayer>,
+    data: Res<SceneData>,
+    projectile_assets: Res<ProjectileAssets>,
+    mut weapons: Query<(&mut Weapon, &GlobalTransform)>,
+    mut gimbles: Query<(&mut Gimble, &mut Transform)>,
+    critters: Query<(Entity, &Critter, &GlobalTransform, Option<&Parent>)>,
+    mut hits: EventWriter<HitEvent>,
+) {
+    let Ok((mut weapon, camera)) = weapons.get_mut(player.camera_id) else {
+        return;
+    };
+    weapon.cooldown = (weapon.cooldown - time.delta_seconds()).max(0.0);
+    if !actions.pressed(Action::Fire) {
+        lock.0 = false;
+        return;
+    }
+    if lock.0 || weapon.cooldown > 0.0 {
+        return;
+    }
+    weapon.cooldown = 1.0 / weapon.fire_rate;
+
+    let origin = camera.translation();
+    let direction = scatter(camera.forward(), camera.right(), camera.up(), weapon.spread);
+
+    if let Ok((mut gimble, mut transform)) = gimbles.get_mut(player.gimble_id) {
+        gimble.theta = (gimble.theta + weapon.recoil).min(VIEW_LOCK);
+        transform.rotation = Quat::from_rotation_x(gimble.theta);
+    }
+
+    if let WeaponKind::Launcher(kind) = weapon.kind {
+        spawn_projectile(
+            &mut commands,
+            &projectile_assets,
+            player.id,
+            kind,
+            origin + direction * 0.2,
+            direction,
+        );
+        return;
+    }
+
+    let mut nearest = raycast(&data, origin, direction, weapon.range).map(|hit| {
+        let target = match hit.cell {
+            Some((x, z)) => HitTarget::Block { x, z },
+            None => HitTarget::Floor,
+        };
+        (hit.distance, target)
+    });
+    for (entity, critter, transform, parent) in critters.iter() {
+        // Don't shoot our own legs
+        if parent.map(|parent| parent.get()) == Some(player.id) {
+            continue;
+        }
+        let body = transform.translation();
+        let distance = critter
+            .bones()
+            .filter_map(|(a, b)| ray_capsule(origin, direction, a, b, CRITTER_LEG_RADIUS))
+            .chain(ray_sphere(origin, direction, body, CRITTER_BODY_RADIUS))
+            .reduce(f32::min);
+        if let Some(distance) = distance {
+            if nearest.map_or(distance <= weapon.range, |(nearest, _)| distance < nearest) {
+                nearest = Some((distance, HitTarget::Critter(entity)));
+            }
+        }
+    }
+    if let Some((distance, target)) = nearest {
+        hits.send(HitEvent {
+            shooter: player.id,
+            target,
+            point: origin + direction * distance,
+            direction,
+            damage: weapon.damage,
+        });
+    }
+}
+
+/// A random direction in a cone of half angle `spread` around `forward`.
+fn scatter(forward: Vec3, right: Vec3, up: Vec3, spread: f32) -> Vec3 {
+    let mut rng = thread_rng();
+    let angle = rng.gen_range(0.0..TAU);
+    let radius = (spread * rng.gen_range(0.0f32..1.0).sqrt()).tan();
+    let (s, c) = angle.sin_cos();
+    (forward + (right * c + up * s) * radius).normalize()
+}
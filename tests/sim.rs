@@ -0,0 +1,349 @@
+//! Drives [`GameSimPlugin`] headless with synthetic keyboard input, no window or GPU needed.
+
+use std::time::{Duration, Instant};
+
+use bevy::{
+    input::{keyboard::KeyboardInput, ButtonState, InputPlugin},
+    prelude::*,
+    window::WindowFocused,
+};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use shooter_game::{
+    actions::{Action, ActionsPlugin, Binding, Bindings, InputConfig, InputSource},
+    collision::Collider,
+    critter::{make_cirtter, Critter, CritterRng, CritterSpec},
+    gait::{GaitKind, GaitSpeed},
+    movement::Movement,
+    scene::SceneData,
+    sim::{run_ticks, GameSimPlugin, MainPlayer, MoveInput, Physics},
+    state::{GameState, GameStatePlugin},
+};
+
+/// An arena of `data` with a player standing at its center, and a critter of `critter` on the
+/// player when there is one.
+fn sim_app(data: SceneData, critter: Option<CritterSpec>) -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(TransformPlugin)
+        .add_plugin(InputPlugin)
+        .add_plugin(AssetPlugin::default())
+        .add_plugin(ActionsPlugin)
+        .add_asset::<Mesh>()
+        .add_asset::<StandardMaterial>()
+        .insert_resource(data)
+        .insert_resource(CritterRng(ChaCha8Rng::seed_from_u64(0)))
+        .add_plugin(GameSimPlugin)
+        .insert_resource(NextState(Some(GameState::Playing)));
+    app.add_startup_system(
+        move |mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>| {
+            let position = Vec3::ZERO;
+            let id = commands
+                .spawn((
+                    Physics::at(position),
+                    Collider::default(),
+                    MoveInput::default(),
+                    Movement::default(),
+                    TransformBundle::from_transform(Transform::from_translation(position)),
+                ))
+                .id();
+            if let Some(spec) = critter.clone() {
+                let material = Handle::<StandardMaterial>::default();
+                let body = make_cirtter(&mut commands, spec, material, &mut meshes);
+                commands.entity(id).add_child(body);
+            }
+            let placeholder = commands.spawn_empty().id();
+            commands.insert_resource(MainPlayer {
+                id,
+                gimble_id: placeholder,
+                camera_id: placeholder,
+            });
+        },
+    );
+    app
+}
+
+fn key(app: &mut App, key_code: KeyCode, state: ButtonState) {
+    app.world.send_event(KeyboardInput {
+        scan_code: 0,
+        key_code: Some(key_code),
+        state,
+    });
+}
+
+/// Run until the bindings file has loaded, so it can't replace bindings set by the test.
+fn wait_for_bindings(app: &mut App) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        run_ticks(app, 1);
+        let file = &app.world.resource::<Bindings>().file;
+        if app.world.resource::<Assets<InputConfig>>().contains(file) {
+            break;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "config/player.input.ron never loaded"
+        );
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    // One more update for the loaded config to be applied
+    run_ticks(app, 1);
+}
+
+fn state(app: &App) -> GameState {
+    app.world.resource::<State<GameState>>().0
+}
+
+fn player(app: &mut App) -> &Physics {
+    let id = app.world.resource::<MainPlayer>().id;
+    app.world.get::<Physics>(id).unwrap()
+}
+
+#[test]
+fn falling_body_comes_to_rest_on_the_floor() {
+    let mut app = sim_app(SceneData::centered(8, 8, 1.0), None);
+    run_ticks(&mut app, 1);
+    let id = app.world.resource::<MainPlayer>().id;
+    app.world
+        .get_mut::<Physics>(id)
+        .unwrap()
+        .teleport(Vec3::new(0.0, 2.0, 0.0));
+
+    run_ticks(&mut app, 120);
+    let physics = player(&mut app);
+    assert!(physics.on_ground);
+    assert!(physics.position.y.abs() < 1e-3, "{}", physics.position);
+    assert!(physics.velocity.length() < 1e-3, "{}", physics.velocity);
+}
+
+#[test]
+fn holding_forward_walks_the_player_forward() {
+    let mut app = sim_app(SceneData::centered(16, 16, 1.0), None);
+    run_ticks(&mut app, 10);
+    key(&mut app, KeyCode::W, ButtonState::Pressed);
+    run_ticks(&mut app, 60);
+    let walked = player(&mut app).position;
+    assert!(walked.z < -1.0, "{walked}");
+    assert!(walked.x.abs() < 1e-3, "{walked}");
+
+    key(&mut app, KeyCode::W, ButtonState::Released);
+    run_ticks(&mut app, 60);
+    let stopped = player(&mut app).position;
+    assert!(
+        (stopped.z - walked.z).abs() < 0.5,
+        "slid from {walked} to {stopped}"
+    );
+
+    // Sprinting covers more ground in the same time
+    key(&mut app, KeyCode::W, ButtonState::Pressed);
+    key(&mut app, KeyCode::LShift, ButtonState::Pressed);
+    run_ticks(&mut app, 60);
+    let sprinted = player(&mut app).position;
+    assert!(stopped.z - sprinted.z > walked.z.abs());
+}
+
+#[test]
+fn tapping_jump_leaves_the_ground_once() {
+    let mut app = sim_app(SceneData::centered(8, 8, 1.0), None);
+    run_ticks(&mut app, 10);
+    assert!(player(&mut app).on_ground);
+    key(&mut app, KeyCode::Space, ButtonState::Pressed);
+    run_ticks(&mut app, 1);
+    key(&mut app, KeyCode::Space, ButtonState::Released);
+
+    let mut peak = 0.0f32;
+    for _ in 0..30 {
+        run_ticks(&mut app, 1);
+        peak = peak.max(player(&mut app).position.y);
+    }
+    assert!(peak > 0.1, "{peak}");
+    run_ticks(&mut app, 60);
+    let physics = player(&mut app);
+    assert!(physics.on_ground);
+    assert!(physics.position.y.abs() < 1e-3);
+}
+
+#[test]
+fn blocks_stop_the_player() {
+    let mut data = SceneData::centered(8, 8, 1.0);
+    // A wall across the arena just in front of the player
+    for x in 0..data.width() {
+        data.set(x, 2, 1.0);
+    }
+    let wall = data.cell_center(0, 2).y + 0.5;
+    let mut app = sim_app(data, None);
+    run_ticks(&mut app, 1);
+    key(&mut app, KeyCode::W, ButtonState::Pressed);
+    run_ticks(&mut app, 180);
+    let physics = player(&mut app);
+    assert!(physics.position.z >= wall + Collider::default().radius - 1e-3);
+    assert!(physics.position.z < wall + 0.5, "{}", physics.position);
+}
+
+#[test]
+fn critter_legs_follow_the_player() {
+    let mut app = sim_app(
+        SceneData::centered(32, 32, 1.0),
+        Some(CritterSpec::default()),
+    );
+    run_ticks(&mut app, 10);
+    key(&mut app, KeyCode::W, ButtonState::Pressed);
+    run_ticks(&mut app, 240);
+
+    let position = player(&mut app).position;
+    assert!(position.z < -3.0, "{position}");
+    let world = &mut app.world;
+    let critter = world.query::<&Critter>().single(world);
+    assert!(critter.velocity.z < 0.0);
+    for leg in critter.legs() {
+        let reach = (leg.foot() - position) * Vec3::new(1.0, 0.0, 1.0);
+        assert!(reach.length() < 1.0, "foot left behind at {}", leg.foot());
+    }
+}
+
+#[test]
+fn every_gait_keeps_enough_feet_planted() {
+    for kind in [
+        GaitKind::Tripod,
+        GaitKind::Wave,
+        GaitKind::Ripple,
+        GaitKind::Gallop,
+    ] {
+        for legs in [4, 6, 8] {
+            let spec = CritterSpec {
+                legs,
+                gaits: vec![GaitSpeed {
+                    min_speed: 0.0,
+                    gait: kind,
+                }],
+                ..default()
+            };
+            let mut app = sim_app(SceneData::centered(32, 32, 1.0), Some(spec));
+            run_ticks(&mut app, 10);
+            key(&mut app, KeyCode::W, ButtonState::Pressed);
+            let min_grounded = kind.gait().min_grounded(legs);
+            let mut stepped = false;
+            for tick in 0..240 {
+                if tick == 120 {
+                    key(&mut app, KeyCode::LShift, ButtonState::Pressed);
+                }
+                run_ticks(&mut app, 1);
+                let world = &mut app.world;
+                let critter = world.query::<&Critter>().single(world);
+                let planted = critter.legs().iter().filter(|leg| leg.planted()).count();
+                assert!(
+                    planted >= min_grounded,
+                    "{kind:?} with {legs} legs had {planted} feet down on tick {tick}"
+                );
+                stepped |= planted < legs;
+            }
+            assert!(stepped, "{kind:?} with {legs} legs never stepped");
+        }
+    }
+}
+
+#[test]
+fn same_input_gives_the_same_run() {
+    let run = || {
+        let mut app = sim_app(
+            SceneData::centered(16, 16, 1.0),
+            Some(CritterSpec::default()),
+        );
+        run_ticks(&mut app, 5);
+        key(&mut app, KeyCode::W, ButtonState::Pressed);
+        key(&mut app, KeyCode::D, ButtonState::Pressed);
+        run_ticks(&mut app, 40);
+        key(&mut app, KeyCode::Space, ButtonState::Pressed);
+        run_ticks(&mut app, 40);
+        let position = player(&mut app).position;
+        let world = &mut app.world;
+        let critter = world.query::<&Critter>().single(world);
+        let feet: Vec<Vec3> = critter.legs().iter().map(|leg| leg.foot()).collect();
+        (position, feet)
+    };
+    assert_eq!(run(), run());
+}
+
+#[test]
+fn bindings_can_change_while_running() {
+    let mut app = sim_app(SceneData::centered(16, 16, 1.0), None);
+    wait_for_bindings(&mut app);
+
+    app.world.resource_mut::<Bindings>().config.bindings = vec![Binding {
+        action: Action::MoveForward,
+        source: InputSource::Key(KeyCode::Up),
+        scale: 1.0,
+    }];
+    key(&mut app, KeyCode::W, ButtonState::Pressed);
+    run_ticks(&mut app, 30);
+    assert!(player(&mut app).position.z.abs() < 1e-3);
+
+    key(&mut app, KeyCode::Up, ButtonState::Pressed);
+    run_ticks(&mut app, 30);
+    assert!(player(&mut app).position.z < -0.2);
+}
+
+#[test]
+fn pausing_freezes_the_simulation() {
+    let mut app = sim_app(
+        SceneData::centered(16, 16, 1.0),
+        Some(CritterSpec::default()),
+    );
+    run_ticks(&mut app, 10);
+    key(&mut app, KeyCode::W, ButtonState::Pressed);
+    run_ticks(&mut app, 30);
+    app.insert_resource(NextState(Some(GameState::Paused)));
+    run_ticks(&mut app, 1);
+    let paused_at = player(&mut app).position;
+    let feet = |app: &mut App| {
+        let world = &mut app.world;
+        let critter = world.query::<&Critter>().single(world);
+        critter
+            .legs()
+            .iter()
+            .map(|leg| leg.foot())
+            .collect::<Vec<_>>()
+    };
+    let paused_feet = feet(&mut app);
+
+    // Keys held or pressed while paused don't do anything either
+    key(&mut app, KeyCode::Space, ButtonState::Pressed);
+    run_ticks(&mut app, 60);
+    assert_eq!(player(&mut app).position, paused_at);
+    assert_eq!(feet(&mut app), paused_feet);
+
+    app.insert_resource(NextState(Some(GameState::Playing)));
+    run_ticks(&mut app, 30);
+    let resumed = player(&mut app);
+    assert!(resumed.position.z < paused_at.z);
+    assert!(resumed.on_ground, "jumped while paused");
+}
+
+#[test]
+fn pause_and_confirm_follow_the_bindings() {
+    let mut app = sim_app(SceneData::centered(16, 16, 1.0), None);
+    app.add_event::<WindowFocused>().add_plugin(GameStatePlugin);
+    wait_for_bindings(&mut app);
+    assert_eq!(state(&app), GameState::Playing);
+
+    key(&mut app, KeyCode::Escape, ButtonState::Pressed);
+    run_ticks(&mut app, 2);
+    assert_eq!(state(&app), GameState::Paused);
+    key(&mut app, KeyCode::Escape, ButtonState::Released);
+    key(&mut app, KeyCode::Return, ButtonState::Pressed);
+    run_ticks(&mut app, 2);
+    assert_eq!(state(&app), GameState::Playing);
+    key(&mut app, KeyCode::Return, ButtonState::Released);
+
+    app.world.resource_mut::<Bindings>().config.bindings = vec![Binding {
+        action: Action::Pause,
+        source: InputSource::Key(KeyCode::P),
+        scale: 1.0,
+    }];
+    key(&mut app, KeyCode::Escape, ButtonState::Pressed);
+    run_ticks(&mut app, 2);
+    assert_eq!(state(&app), GameState::Playing);
+    key(&mut app, KeyCode::P, ButtonState::Pressed);
+    run_ticks(&mut app, 2);
+    assert_eq!(state(&app), GameState::Paused);
+}